@@ -1,5 +1,13 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use flexss::{rendevouz_shuffle::RendevouzShuffle, BackendId, Health, Picker, TenantId};
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use flexss::{
+    concurrent::ConcurrentRendevouzShuffle,
+    maglev::{Maglev, MaglevShuffle},
+    rendevouz_shuffle::RendevouzShuffle,
+    ring::{Ring, RingShuffle},
+    BackendId, Health, Picker, TenantId,
+};
 
 fn rendevouz_shuffle(c: &mut Criterion) {
     for (name, n, k) in [
@@ -7,7 +15,7 @@ fn rendevouz_shuffle(c: &mut Criterion) {
         ("midsize", 200, 20),
         ("small", 30, 6),
     ] {
-        let mut p = RendevouzShuffle::new(k);
+        let mut p: RendevouzShuffle = Picker::new(k);
         let backends: Vec<BackendId> = (0..n).map(BackendId).collect();
         for &b in &backends {
             p.register(b, Health::Up);
@@ -19,9 +27,95 @@ fn rendevouz_shuffle(c: &mut Criterion) {
     }
 }
 
+fn maglev(c: &mut Criterion) {
+    for (name, n, k) in [
+        ("large", 1_000, 100),
+        ("midsize", 200, 20),
+        ("small", 30, 6),
+    ] {
+        let mut p: Maglev = Picker::new(k);
+        let mut shuffle: MaglevShuffle = Picker::new(k);
+        for b in (0..n).map(BackendId) {
+            p.register(b, Health::Up);
+            shuffle.register(b, Health::Up);
+        }
+        let tenant_id = TenantId(0);
+        c.bench_function(&format!("maglev_{name}"), |b| {
+            b.iter(|| black_box(p.pick(tenant_id)))
+        });
+        c.bench_function(&format!("maglev_shuffle_{name}"), |b| {
+            b.iter(|| black_box(shuffle.pick(tenant_id)))
+        });
+    }
+}
+
+fn ring(c: &mut Criterion) {
+    for (name, n, k) in [
+        ("large", 1_000, 100),
+        ("midsize", 200, 20),
+        ("small", 30, 6),
+    ] {
+        let mut p: Ring = Picker::new(k);
+        let mut shuffle: RingShuffle = Picker::new(k);
+        for b in (0..n).map(BackendId) {
+            p.register(b, Health::Up);
+            shuffle.register(b, Health::Up);
+        }
+        let tenant_id = TenantId(0);
+        c.bench_function(&format!("ring_{name}"), |b| {
+            b.iter(|| black_box(p.pick(tenant_id)))
+        });
+        c.bench_function(&format!("ring_shuffle_{name}"), |b| {
+            b.iter(|| black_box(shuffle.pick(tenant_id)))
+        });
+    }
+}
+
+fn concurrent_rendevouz_shuffle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("concurrent_rendevouz_shuffle");
+    for (name, n, k) in [
+        ("large", 1_000, 100),
+        ("midsize", 200, 20),
+        ("small", 30, 6),
+    ] {
+        let p: ConcurrentRendevouzShuffle = ConcurrentRendevouzShuffle::new(k);
+        for b in (0..n).map(BackendId) {
+            p.register(b, Health::Up);
+        }
+        for threads in [1, 4, 16] {
+            // Every thread performs `iters` picks, so one iteration is `threads` picks.
+            group.throughput(Throughput::Elements(threads));
+            group.bench_function(format!("{name}_{threads}_threads"), |b| {
+                b.iter_custom(|iters| {
+                    std::thread::scope(|s| {
+                        let workers: Vec<_> = (0..threads)
+                            .map(|t| {
+                                let p = &p;
+                                s.spawn(move || {
+                                    let start = Instant::now();
+                                    for i in 0..iters {
+                                        let _ = black_box(p.pick(TenantId(t * iters + i)));
+                                    }
+                                    start.elapsed()
+                                })
+                            })
+                            .collect();
+                        workers
+                            .into_iter()
+                            .map(|w| w.join().unwrap())
+                            .max()
+                            .unwrap_or(Duration::ZERO)
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default();
-    targets = rendevouz_shuffle,
+    targets = rendevouz_shuffle, maglev, ring, concurrent_rendevouz_shuffle,
 }
 criterion_main!(benches);
@@ -1,93 +1,159 @@
-use std::collections::BTreeMap;
+use std::str::FromStr;
 
 use flexss::{
-    self, block_picker::BlockPicker, naive_shuffle::NaiveShuffle, BackendId, Health, Picker,
-    TenantId,
+    metrics::{self, Isolation},
+    registry, Picker,
 };
-use rand::{rngs::SmallRng, Rng, SeedableRng};
-fn main() {
-    println!(
-        "[NaiveShuffle] Load Balancing: {}",
-        quantify_load_balancing::<NaiveShuffle>()
-    );
-    println!(
-        "[NaiveShuffle] Tenant Isolation: {:?}",
-        quantify_tenant_isolation::<NaiveShuffle>()
-    );
+use serde::Serialize;
 
-    println!(
-        "[BlockPicker] Load Balancing: {}",
-        quantify_load_balancing::<BlockPicker>()
-    );
-    println!(
-        "[BlockPicker] Tenant Isolation: {:?}",
-        quantify_tenant_isolation::<BlockPicker>()
-    );
-}
+/// Pickers reported when `--picker` is not given, and whether each one has shards to isolate tenants with.
+const DEFAULT_PICKERS: &[(&str, bool)] = &[
+    ("naive-shuffle", true),
+    ("block", true),
+    ("rendezvous-shuffle", true),
+    ("jump-shuffle", true),
+    ("multi-probe-shuffle", true),
+    ("ring-shuffle", true),
+    // Single-backend consistent hashes have no shard to isolate tenants with.
+    ("jump", false),
+    ("multi-probe", false),
+    ("ring", false),
+    // Power-of-two-choices keeps the same shards, so only load balancing changes.
+    ("least-loaded-naive-shuffle", false),
+    ("least-loaded-rendezvous-shuffle", false),
+];
 
-fn quantify_load_balancing<P: Picker>() -> f64 {
-    let num_backends = 10;
-    let mut p = P::new(3);
-    for i in 0..num_backends {
-        p.register(BackendId(i as u64), Health::Up);
-    }
+/// Reports load balancing and tenant isolation for `--picker` (or a default set of pickers). `--shard-size`,
+/// `--backends` and `--tenants` override the fleet each measurement runs against, and `--format` prints the results
+/// as `text`, `json` or `csv`.
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let shard_size = parsed(&args, "--shard-size").unwrap_or(3);
+    let pickers: Vec<(&str, bool)> = match flag(&args, "--picker") {
+        Some(name) => vec![(name, true)],
+        None => DEFAULT_PICKERS.to_vec(),
+    };
 
-    let mut tally: Vec<usize> = vec![0; num_backends];
-    let mut prng = SmallRng::seed_from_u64(42);
-    for _ in 0..100_000 {
-        let choice = p.pick(TenantId(prng.gen_range(0..1_000))).unwrap();
-        tally[choice.0 as usize] += 1;
+    let mut results = Vec::new();
+    for (name, sharded) in pickers {
+        let mut p = build(name, shard_size);
+        let load_balancing = metrics::load_balancing(
+            &mut *p,
+            parsed(&args, "--backends").unwrap_or(10),
+            parsed(&args, "--tenants").unwrap_or(1_000),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("[{name}] load balancing: {e:#}");
+            std::process::exit(1);
+        });
+        let tenant_isolation = sharded.then(|| {
+            let mut oracle = build(name, shard_size);
+            metrics::tenant_isolation(
+                &mut *oracle,
+                shard_size,
+                parsed(&args, "--backends").unwrap_or(100),
+                parsed(&args, "--tenants").unwrap_or(100),
+            )
+        });
+        results.push(Quantified {
+            picker: name.to_string(),
+            load_balancing,
+            tenant_isolation,
+        });
     }
 
-    let mean = tally.iter().copied().sum::<usize>() as f64 / num_backends as f64;
-    let variance = tally
-        .iter()
-        .copied()
-        .map(|x| {
-            let dx = x as f64 - mean;
-            dx * dx
-        })
-        .sum::<f64>()
-        / num_backends as f64;
-    variance.sqrt()
+    match flag(&args, "--format").unwrap_or("text") {
+        "json" => println!("{}", serde_json::to_string_pretty(&results).unwrap()),
+        "csv" => write_csv(&results).unwrap(),
+        "text" => {
+            for q in &results {
+                println!("[{}] Load Balancing: {}", q.picker, q.load_balancing);
+                if let Some(isolation) = &q.tenant_isolation {
+                    println!("[{}] Tenant Isolation: {:?}", q.picker, isolation.overlaps);
+                    println!(
+                        "[{}] Random Shards: {:?}",
+                        q.picker, isolation.expected_overlaps
+                    );
+                    println!(
+                        "[{}] Poison-Pill Takeout: {} (random shards: {})",
+                        q.picker, isolation.takeout, isolation.expected_takeout
+                    );
+                }
+            }
+        }
+        format => panic!("--format expects text, json or csv, got {format:?}"),
+    }
 }
 
-fn quantify_tenant_isolation<P: Picker>() -> Vec<f64> {
-    let shard_size = 3;
-    let mut oracle = P::new(shard_size);
-    for i in 0..100 {
-        oracle.register(BackendId(i), Health::Up);
-    }
+#[derive(Serialize)]
+struct Quantified {
+    picker: String,
+    /// Standard deviation of requests per backend.
+    load_balancing: f64,
+    tenant_isolation: Option<Isolation>,
+}
 
-    let num_tenants = 100;
-    let mut tally: BTreeMap<TenantId, BTreeMap<BackendId, usize>> = BTreeMap::new();
-    for tenant_id in 0..num_tenants {
-        let tenant_id = TenantId(tenant_id);
-        for _ in 0..1_000 {
-            let backend_id = oracle.pick(tenant_id).unwrap();
-            *tally
-                .entry(tenant_id)
-                .or_default()
-                .entry(backend_id)
-                .or_default() += 1;
-        }
-    }
-    let mut overlaps = vec![0; shard_size + 1];
-    for (tenant_1, backends_1) in &tally {
-        for (tenant_2, backends_2) in &tally {
-            if tenant_1 == tenant_2 {
-                continue;
+/// Same long form as the simulator's reports: one `picker,metric,key,value` row per measurement.
+fn write_csv(results: &[Quantified]) -> anyhow::Result<()> {
+    let mut w = csv::Writer::from_writer(std::io::stdout());
+    w.write_record(["picker", "metric", "key", "value"])?;
+    for q in results {
+        w.write_record([
+            &q.picker,
+            "load_balancing",
+            "",
+            &q.load_balancing.to_string(),
+        ])?;
+        let Some(isolation) = &q.tenant_isolation else {
+            continue;
+        };
+        for (metric, fractions) in [
+            ("tenant_isolation", &isolation.overlaps),
+            ("expected_tenant_isolation", &isolation.expected_overlaps),
+        ] {
+            for (overlap, fraction) in fractions.iter().enumerate() {
+                w.write_record([
+                    &q.picker,
+                    metric,
+                    &overlap.to_string(),
+                    &fraction.to_string(),
+                ])?;
             }
-            let count = backends_1
-                .keys()
-                .filter(|b| backends_2.contains_key(b))
-                .count();
-            overlaps[count] += 1;
         }
+        w.write_record([&q.picker, "takeout", "", &isolation.takeout.to_string()])?;
+        w.write_record([
+            &q.picker,
+            "expected_takeout",
+            "",
+            &isolation.expected_takeout.to_string(),
+        ])?;
     }
-    let normalization = (num_tenants * num_tenants) as f64;
-    overlaps
-        .into_iter()
-        .map(|c| c as f64 / normalization)
-        .collect()
+    w.flush()?;
+    Ok(())
+}
+
+/// Builds the picker called `name`, or exits with the registry's error, which lists the pickers there are.
+fn build(name: &str, shard_size: usize) -> Box<dyn Picker> {
+    registry::picker(name, shard_size).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(2);
+    })
+}
+
+/// The value following `name` on the command line, if any.
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+/// Parses the value following `name` on the command line, if any, or exits if it isn't a number.
+fn parsed<T: FromStr>(args: &[String], name: &str) -> Option<T> {
+    flag(args, name).map(|v| {
+        v.parse().unwrap_or_else(|_| {
+            eprintln!("{name} expects a number, got {v:?}");
+            std::process::exit(2);
+        })
+    })
 }
@@ -0,0 +1,218 @@
+//! Runs every registered picker against every scenario in `scenarios/` and every fleet-wide metric, prints the
+//! results as a matrix and compares it with the checked-in baseline in `scorecard.md`.
+//!
+//! A cell regresses when a scenario that used to pass now fails, when a metric (lower is better) grows at all, or
+//! when it is in the baseline but no longer scored. Every picker is seeded, so an unchanged picker scores exactly
+//! the same every run. Any regression fails the run. Improvements are reported but do not fail; rerun with
+//! `--update` to accept them into the baseline.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use flexss::{metrics, registry, scenario::Scenario};
+
+const PASS: &str = "pass";
+const FAIL: &str = "FAIL";
+
+struct Scorecard {
+    columns: Vec<String>,
+    /// One row per picker, with a cell per column.
+    rows: Vec<(String, Vec<String>)>,
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let baseline_path = flag(&args, "--baseline")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.join("scorecard.md"));
+
+    let scorecard = score(&root.join("scenarios"));
+    match flag(&args, "--format").unwrap_or("terminal") {
+        "terminal" => print!("{}", scorecard.terminal()),
+        "markdown" => print!("{}", scorecard.markdown()),
+        format => panic!("--format expects terminal or markdown, got {format:?}"),
+    }
+
+    if args.iter().any(|a| a == "--update") {
+        std::fs::write(&baseline_path, scorecard.markdown()).unwrap();
+        println!("updated {}", baseline_path.display());
+        return;
+    }
+
+    let baseline = std::fs::read_to_string(&baseline_path)
+        .unwrap_or_else(|e| panic!("reading {}: {e}", baseline_path.display()));
+    let mut baseline = parse_markdown(&baseline);
+    let mut regressions = 0;
+    for (picker, cells) in &scorecard.rows {
+        for (column, cell) in scorecard.columns.iter().zip(cells) {
+            let Some(before) = baseline.remove(&(picker.clone(), column.clone())) else {
+                println!("new: {picker} / {column} = {cell}");
+                continue;
+            };
+            match compare(&before, cell) {
+                Change::Same => {}
+                Change::Better => println!("improved: {picker} / {column}: {before} -> {cell}"),
+                Change::Worse => {
+                    println!("REGRESSED: {picker} / {column}: {before} -> {cell}");
+                    regressions += 1;
+                }
+            }
+        }
+    }
+    // Whatever is left was scored last time but not this time, such as a dropped picker or scenario.
+    for ((picker, column), before) in &baseline {
+        println!("REGRESSED: {picker} / {column}: {before} -> missing");
+        regressions += 1;
+    }
+    if regressions > 0 {
+        println!(
+            "{regressions} cells regressed against {}",
+            baseline_path.display()
+        );
+        std::process::exit(1);
+    }
+}
+
+/// The value following `name` on the command line, if any.
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+fn score(scenario_dir: &Path) -> Scorecard {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(scenario_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    let scenarios: Vec<(String, Scenario)> = paths
+        .iter()
+        .map(|path| {
+            let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+            (stem, Scenario::load(path).unwrap())
+        })
+        .collect();
+
+    let mut columns: Vec<String> = scenarios.iter().map(|(stem, _)| stem.clone()).collect();
+    // Standard deviation of per-backend load, the fraction of tenant pairs whose shards share a backend, and the
+    // chance that a poison-pill tenant takes out another tenant entirely.
+    columns.push("load_stddev".to_string());
+    columns.push("shared_shards".to_string());
+    columns.push("takeout".to_string());
+
+    let rows = registry::names()
+        .map(|name| {
+            let mut cells: Vec<String> = scenarios
+                .iter()
+                .map(|(_, scenario)| {
+                    let mut picker = registry::picker(name, scenario.shard_size).unwrap();
+                    let report = scenario.report_with(name, &mut *picker);
+                    if report.passed { PASS } else { FAIL }.to_string()
+                })
+                .collect();
+
+            let mut picker = registry::picker(name, 3).unwrap();
+            cells.push(format!(
+                "{:.1}",
+                metrics::load_balancing(&mut *picker, 10, 1_000).unwrap()
+            ));
+            let mut oracle = registry::picker(name, 3).unwrap();
+            let isolation = metrics::tenant_isolation(&mut *oracle, 3, 100, 100);
+            cells.push(format!(
+                "{:.4}",
+                isolation.overlaps[1..].iter().sum::<f64>()
+            ));
+            cells.push(format!("{:.4}", isolation.takeout));
+            (name.to_string(), cells)
+        })
+        .collect();
+    Scorecard { columns, rows }
+}
+
+impl Scorecard {
+    fn markdown(&self) -> String {
+        let mut out = format!("| picker | {} |\n", self.columns.join(" | "));
+        out += &format!("|---|{}\n", "---|".repeat(self.columns.len()));
+        for (picker, cells) in &self.rows {
+            out += &format!("| {picker} | {} |\n", cells.join(" | "));
+        }
+        out
+    }
+
+    fn terminal(&self) -> String {
+        let mut lines: Vec<Vec<&str>> = vec![std::iter::once("picker")
+            .chain(self.columns.iter().map(String::as_str))
+            .collect()];
+        for (picker, cells) in &self.rows {
+            lines.push(
+                std::iter::once(picker.as_str())
+                    .chain(cells.iter().map(String::as_str))
+                    .collect(),
+            );
+        }
+        let widths: Vec<usize> = (0..lines[0].len())
+            .map(|i| lines.iter().map(|line| line[i].len()).max().unwrap())
+            .collect();
+        let rule: Vec<String> = widths.iter().map(|&w| "-".repeat(w)).collect();
+        lines.insert(1, rule.iter().map(String::as_str).collect());
+
+        let mut out = String::new();
+        for line in lines {
+            let padded: Vec<String> = line
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &w)| format!("{cell:<w$}"))
+                .collect();
+            out += padded.join("  ").trim_end();
+            out += "\n";
+        }
+        out
+    }
+}
+
+/// Reads a table written by `Scorecard::markdown` back into cells keyed by picker and column.
+fn parse_markdown(s: &str) -> BTreeMap<(String, String), String> {
+    let mut rows = s
+        .lines()
+        .filter(|line| line.starts_with('|') && !line.starts_with("|---"))
+        .map(|line| {
+            line.trim_matches('|')
+                .split('|')
+                .map(|cell| cell.trim().to_string())
+                .collect::<Vec<String>>()
+        });
+    let Some(header) = rows.next() else {
+        return BTreeMap::new();
+    };
+    let mut cells = BTreeMap::new();
+    for row in rows {
+        for (column, cell) in header.iter().zip(&row).skip(1) {
+            cells.insert((row[0].clone(), column.clone()), cell.clone());
+        }
+    }
+    cells
+}
+
+enum Change {
+    Same,
+    Better,
+    Worse,
+}
+
+fn compare(before: &str, after: &str) -> Change {
+    match (before, after) {
+        (PASS, FAIL) => Change::Worse,
+        (FAIL, PASS) => Change::Better,
+        _ => match (before.parse::<f64>(), after.parse::<f64>()) {
+            (Ok(b), Ok(a)) if a > b => Change::Worse,
+            (Ok(b), Ok(a)) if a < b => Change::Better,
+            _ => Change::Same,
+        },
+    }
+}
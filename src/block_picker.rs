@@ -1,53 +1,202 @@
+use std::collections::BTreeMap;
+
 use rand::{rngs::SmallRng, Rng, SeedableRng};
 
-use crate::{hash, Backend, BackendId, Health, Picker, TenantId};
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    spread_shard, weighted_index, Backend, BackendId, Health, Pick, PickError, PickResult, Picker,
+    TenantId,
+};
+
+/// A set of backends that are deployed together, such as a deploy group.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct BlockId(pub u64);
 
-pub struct BlockPicker {
-    backends: Vec<Backend>,
+/// Shuffle sharding over blocks of backends: a tenant's shard takes the tenant's best backend from each block in
+/// turn, so members come from as many different blocks as the shard has room for.
+///
+/// Blocks can be assigned explicitly with `register_in_block`, e.g. one per deploy group. Backends registered
+/// without a block keep the block they already had, or join whichever of the blocks `0..shard_size` has the fewest
+/// members, so a fleet that doesn't divide evenly spreads its remainder over the blocks one backend each.
+///
+/// Within a block, backends are ranked by weighted rendezvous score for the tenant, and draining backends hand
+/// their place to the next one down. Down backends keep theirs, so a poison-pill tenant can't work its way through
+/// a block. As long as there are at least two blocks and shards of at least two, no shard lies entirely within
+/// one block, and taking a whole deploy group out of service never leaves a tenant without a backend.
+pub struct BlockPicker<R = SmallRng, H = SipHash13> {
+    backends: Vec<(BlockId, Backend)>,
     shard_size: usize,
-    prng: SmallRng,
+    shard_seed: u64,
+    hasher: H,
+    prng: R,
 }
-impl Picker for BlockPicker {
-    fn new(shard_size: usize) -> Self {
+impl<R: Rng, H: HashFn + Default> BlockPicker<R, H> {
+    /// Like `Picker::new`, but `prng` decides which block serves each request.
+    pub fn with_rng(shard_size: usize, prng: R) -> Self {
         Self {
-            shard_size,
-            backends: Vec::default(),
-            prng: SmallRng::seed_from_u64(42),
+            backends: Vec::new(),
+            shard_size: shard_size.max(1),
+            shard_seed: 0,
+            hasher: H::default(),
+            prng,
         }
     }
-    fn register(&mut self, id: BackendId, health: Health) {
-        if let Some(existing) = self.backends.iter_mut().find(|b| b.id == id) {
+
+    /// Replaces the hash function that ranks backends, e.g. to use a keyed or seeded variant.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.backends = self
+            .backends
+            .iter()
+            .map(|&(block, b)| (block, b.hashed_with(&hasher)))
+            .collect();
+        self.hasher = hasher;
+        self
+    }
+
+    /// Mixes `shard_seed` into the tenant's rendezvous hash, for both the block order and the ranking within
+    /// each block.
+    pub fn with_shard_seed(mut self, shard_seed: u64) -> Self {
+        self.shard_seed = shard_seed;
+        self
+    }
+
+    pub fn register_in_block(
+        &mut self,
+        id: BackendId,
+        block: BlockId,
+        health: Health,
+        weight: u32,
+    ) {
+        if let Some((existing_block, existing)) = self.backends.iter_mut().find(|(_, b)| b.id == id)
+        {
+            *existing_block = block;
             existing.health = health;
+            existing.weight = weight;
         } else {
-            self.backends.push(Backend {
-                id,
-                health,
-                hash: hash(id),
-            });
-            self.backends.sort();
+            self.backends.push((
+                block,
+                Backend::new(id, health, weight).hashed_with(&self.hasher),
+            ));
+        }
+    }
+
+    /// The block with the fewest members among `0..shard_size`, for backends registered without one.
+    fn smallest_block(&self) -> BlockId {
+        let mut sizes: BTreeMap<BlockId, usize> = (0..self.shard_size.max(1) as u64)
+            .map(|block| (BlockId(block), 0))
+            .collect();
+        for (block, _) in &self.backends {
+            if let Some(n) = sizes.get_mut(block) {
+                *n += 1;
+            }
+        }
+        sizes
+            .into_iter()
+            .min_by_key(|&(block, n)| (n, block))
+            .map(|(block, _)| block)
+            .unwrap_or_default()
+    }
+
+    /// Every block's backends that aren't draining.
+    fn blocks(&self) -> BTreeMap<BlockId, Vec<Backend>> {
+        let mut blocks: BTreeMap<BlockId, Vec<Backend>> = BTreeMap::new();
+        for &(block, b) in &self.backends {
+            if b.health != Health::Draining {
+                blocks.entry(block).or_default().push(b);
+            }
         }
+        blocks
+    }
+
+    /// The tenant's shard, spread across blocks, out of the backends that `eligible` accepts.
+    fn spread(&self, id: TenantId, eligible: impl Fn(&Backend) -> bool) -> Vec<(BlockId, Backend)> {
+        let th = self.hasher.hash_u64(id.0) ^ self.shard_seed;
+        spread_shard(
+            &self.backends,
+            th,
+            |block| self.hasher.hash_u64(block.0),
+            self.shard_size,
+            eligible,
+        )
+    }
+
+    fn shard_members(&self, id: TenantId) -> Vec<(BlockId, Backend)> {
+        self.spread(id, |b| b.health != Health::Draining)
+    }
+
+    /// Whether `b` holds a place in the tenant's shard that a draining backend handed down to it.
+    fn outside_primary_shard(&self, id: TenantId, b: BackendId) -> bool {
+        self.backends
+            .iter()
+            .any(|(_, b)| b.health == Health::Draining)
+            && !self
+                .spread(id, |_| true)
+                .iter()
+                .any(|(_, primary)| primary.id == b)
+    }
+}
+impl<R: Rng + SeedableRng, H: HashFn + Default> Picker for BlockPicker<R, H> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_rng(shard_size, R::seed_from_u64(42))
+    }
+    /// Backends registered without a block keep the block they already had, or join the smallest of
+    /// `0..shard_size`.
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        let block = self
+            .backends
+            .iter()
+            .find(|(_, b)| b.id == id)
+            .map(|&(block, _)| block)
+            .unwrap_or_else(|| self.smallest_block());
+        self.register_in_block(id, block, health, weight);
     }
 
     fn unregister(&mut self, id: BackendId) {
-        self.backends.retain(|b| b.id != id);
+        self.backends.retain(|(_, b)| b.id != id);
     }
 
-    fn pick(&mut self, id: TenantId) -> Option<BackendId> {
-        let bucket_size = self.backends.len() / self.shard_size;
-        if bucket_size == 0 {
-            return None;
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if self.backends.is_empty() {
+            return Err(PickError::NoBackends);
         }
-        let bucket = self.prng.gen_range(0..self.shard_size);
-        for i in 0..self.shard_size {
-            let bucket = (bucket + i) % self.shard_size;
-            // Note: different RNG! This one is determinstic based on the tenant id and bucket.
-            let mut prng = SmallRng::seed_from_u64(id.0 ^ bucket as u64);
-            let slot = prng.gen_range(0..bucket_size);
-            let b = self.backends[bucket * bucket_size + slot];
+        let shard = self.shard_members(id);
+        if shard.is_empty() {
+            return Err(PickError::ShardUnavailable);
+        }
+        // Each block receives traffic in proportion to its total capacity, split between its members in the shard.
+        // Membership within a block is already weighted, so every backend's share stays in line with its weight
+        // even when the blocks differ in size.
+        let capacity: BTreeMap<BlockId, u64> = self
+            .blocks()
+            .into_iter()
+            .map(|(block, members)| (block, members.iter().map(|b| b.weight as u64).sum()))
+            .collect();
+        let weights: Vec<u64> = shard
+            .iter()
+            .map(|(block, _)| {
+                let members = shard.iter().filter(|(other, _)| other == block).count() as u64;
+                capacity[block] * self.shard_size as u64 / members
+            })
+            .collect();
+        let start = weighted_index(&weights, &mut self.prng);
+        for i in 0..shard.len() {
+            let (_, b) = shard[(start + i) % shard.len()];
             if b.health == Health::Up {
-                return Some(b.id);
+                return Ok(Pick::new(b.id, self.outside_primary_shard(id, b.id)));
             }
         }
-        None
+        Err(PickError::ShardUnavailable)
+    }
+
+    /// The tenant's members, in the order they are drawn from the blocks.
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.shard_members(id)
+            .into_iter()
+            .map(|(_, b)| (b.id, b.health))
+            .collect()
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.outside_primary_shard(id, backend)
     }
 }
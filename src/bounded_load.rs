@@ -0,0 +1,117 @@
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    weighted_score, Backend, BackendId, Health, Pick, PickError, PickResult, Picker, TenantId,
+};
+
+/// The default slack over the average load before a backend is skipped.
+pub const DEFAULT_EPSILON: f64 = 0.25;
+
+/// Consistent hashing with bounded loads (Mirrokni, Thorup & Zadimoghaddam), on top of weighted rendezvous hashing.
+///
+/// Every pick counts as in flight until the caller calls `on_request_complete`. A backend may hold at most
+/// `⌈(1 + ε) · (in_flight + 1) · weight / total_weight⌉` requests; a tenant goes to the highest-ranked healthy
+/// backend with room, so a hot tenant spills over onto its next rendezvous choices instead of overloading one
+/// backend, and an idle fleet routes exactly like `Rendevouz`.
+pub struct BoundedLoadRendezvous<H = SipHash13> {
+    backends: Vec<Backend>,
+    in_flight: BTreeMap<BackendId, u64>,
+    epsilon: f64,
+    hasher: H,
+}
+
+impl<H: HashFn> BoundedLoadRendezvous<H> {
+    /// Sets the slack ε. Smaller values balance load more tightly at the cost of moving more tenants off their
+    /// preferred backend.
+    pub fn with_epsilon(mut self, epsilon: f64) -> Self {
+        assert!(epsilon > 0.0, "epsilon must be positive");
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// Replaces the hash function, e.g. to use a keyed or seeded variant.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    fn score(&self, id: TenantId, b: &Backend) -> u64 {
+        weighted_score(self.hasher.hash_pair(id.0, b.id.0), b.weight)
+    }
+}
+
+impl<H: HashFn + Default> Picker for BoundedLoadRendezvous<H> {
+    fn new(_shard_size: usize) -> Self {
+        Self {
+            backends: Vec::new(),
+            in_flight: BTreeMap::new(),
+            epsilon: DEFAULT_EPSILON,
+            hasher: H::default(),
+        }
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        if let Some(existing) = self.backends.iter_mut().find(|b| b.id == id) {
+            existing.health = health;
+            existing.weight = weight;
+        } else {
+            self.backends.push(Backend::new(id, health, weight));
+        }
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.backends.retain(|b| b.id != id);
+        self.in_flight.remove(&id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        let top = self
+            .backends
+            .iter()
+            .max_by_key(|b| self.score(id, b))
+            .ok_or(PickError::NoBackends)?
+            .id;
+        let healthy: Vec<&Backend> = self
+            .backends
+            .iter()
+            .filter(|b| b.health == Health::Up)
+            .collect();
+        let load = |b: &Backend| self.in_flight.get(&b.id).copied().unwrap_or_default();
+        let total_load: u64 = healthy.iter().map(|b| load(b)).sum();
+        let total_weight: u64 = healthy.iter().map(|b| b.weight as u64).sum();
+        let budget = (1.0 + self.epsilon) * (total_load + 1) as f64 / total_weight as f64;
+
+        // The highest-ranked backend with room is the highest-scoring one among those with room. The capacities
+        // add up to more than the current load, so there always is one.
+        let choice = healthy
+            .into_iter()
+            .filter(|b| (load(b) as f64) < (budget * b.weight as f64).ceil())
+            .max_by_key(|b| self.score(id, b))
+            .ok_or(PickError::ShardUnavailable)?
+            .id;
+        *self.in_flight.entry(choice).or_default() += 1;
+        // Spilling over to a lower-ranked backend because the top one is full counts as falling back too.
+        Ok(Pick::new(choice, choice != top))
+    }
+
+    /// The whole fleet, ranked. Tenants spill down the ranking as the backends above them fill up.
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        let mut ranked = self.backends.clone();
+        ranked.sort_by_key(|b| Reverse(self.score(id, b)));
+        ranked.into_iter().map(|b| (b.id, b.health)).collect()
+    }
+
+    /// Anything but the top-ranked backend, whether it is down or only full.
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.backends
+            .iter()
+            .max_by_key(|b| self.score(id, b))
+            .is_some_and(|top| top.id != backend)
+    }
+
+    fn on_request_complete(&mut self, id: BackendId) {
+        if let Some(n) = self.in_flight.get_mut(&id) {
+            *n = n.saturating_sub(1);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! Cell-based routing: tenants are assigned to independent cells of backends, each with its own picker.
+//!
+//! A cell is a self-contained copy of the service. Whatever goes wrong inside one cell, whether a poison-pill tenant,
+//! a bad deploy or an overload, stays inside it. Within its cell, a tenant is routed by the cell's own picker,
+//! typically a shuffle sharder, so a problem tenant only reaches its shard of its cell.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    rendevouz::Rendevouz, BackendId, Health, Outcome, Pick, PickError, PickResult, Picker,
+    TenantId, DEFAULT_WEIGHT,
+};
+
+/// The number of cells `Picker::new` opens.
+pub const DEFAULT_CELLS: u64 = 3;
+
+/// An independent group of backends.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct CellId(pub u64);
+
+/// How moving a tenant from one cell to another changes what it can reach.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    pub tenant: TenantId,
+    pub from: Option<CellId>,
+    pub to: Option<CellId>,
+    /// The tenant's shard before and after the move.
+    pub before: Vec<BackendId>,
+    pub after: Vec<BackendId>,
+    /// Other tracked tenants that share a backend with the tenant after the move but didn't before. A poison pill
+    /// would take them down with it.
+    pub exposed: Vec<TenantId>,
+}
+
+struct Cell<P> {
+    picker: P,
+    backends: BTreeSet<BackendId>,
+}
+
+/// Where every tenant the router knows about lives, and its shard there.
+type Placements = BTreeMap<TenantId, (Option<CellId>, Vec<BackendId>)>;
+
+/// Routes each tenant to a cell with the assignment picker `A`, and then to a backend with that cell's `P`.
+///
+/// `A` sees every cell as a backend, `BackendId(cell.0)`, and a tenant lives in the first cell of its shard there.
+/// With a consistent hash like `Rendevouz`, adding or retiring a cell only moves the tenants that move into or out
+/// of it. Tenants can also be pinned to a cell, which is how one is migrated. Operations that move tenants return a
+/// `Migration` for every tracked tenant that moved: those registered with `register_tenant`, and those pinned. Working
+/// out who moved means recomputing every tracked tenant's shard, so only tenants worth reporting on should be
+/// registered.
+///
+/// A cell without any backends, such as one that was opened but not yet populated, passes its tenants on to the next
+/// cell in their order, and those picks are marked as fallbacks. A cell whose backends are all down does not: its
+/// tenants fail with `PickError::ShardUnavailable`, as they would with the cell's picker on its own.
+pub struct CellRouter<P, A = Rendevouz> {
+    assignment: A,
+    cells: BTreeMap<CellId, Cell<P>>,
+    /// The cell every registered backend belongs to.
+    backend_cells: BTreeMap<BackendId, CellId>,
+    pinned: BTreeMap<TenantId, CellId>,
+    /// Tenants registered with `register_tenant`, whose moves migrations report along with those of pinned tenants.
+    tenants: BTreeSet<TenantId>,
+    shard_size: usize,
+}
+
+impl<P: Picker, A: Picker> CellRouter<P, A> {
+    /// Opens cells `0..cells`, with no backends yet, whose pickers build shards of `shard_size`.
+    pub fn with_cells(shard_size: usize, cells: u64) -> Self {
+        let mut router = Self {
+            // Each tenant only ever uses the first cell of its shard, so the shard size doesn't matter here.
+            assignment: A::new(1),
+            cells: BTreeMap::new(),
+            backend_cells: BTreeMap::new(),
+            pinned: BTreeMap::new(),
+            tenants: BTreeSet::new(),
+            shard_size,
+        };
+        for cell in 0..cells {
+            router.open(CellId(cell));
+        }
+        router
+    }
+
+    fn open(&mut self, cell: CellId) {
+        assert!(!self.cells.contains_key(&cell), "{cell:?} already exists");
+        self.cells.insert(
+            cell,
+            Cell {
+                picker: P::new(self.shard_size),
+                backends: BTreeSet::new(),
+            },
+        );
+        self.assignment.register(BackendId(cell.0), Health::Up);
+    }
+
+    /// Opens `cell` with `backends`, all up, and moves over the tenants that now belong to it.
+    pub fn add_cell(&mut self, cell: CellId, backends: &[BackendId]) -> Vec<Migration> {
+        let tracked = self.tracked();
+        let before = self.placements(&tracked);
+        self.open(cell);
+        for &b in backends {
+            self.register_in_cell(b, cell, Health::Up, DEFAULT_WEIGHT);
+        }
+        self.migrations(&tracked, &before)
+    }
+
+    /// Closes `cell`, unregistering its backends, and moves its tenants to their next cell. Tenants pinned to it
+    /// are unpinned.
+    pub fn retire_cell(&mut self, cell: CellId) -> Vec<Migration> {
+        let tracked = self.tracked();
+        let before = self.placements(&tracked);
+        let Some(retired) = self.cells.remove(&cell) else {
+            return Vec::new();
+        };
+        for b in retired.backends {
+            self.backend_cells.remove(&b);
+        }
+        self.pinned.retain(|_, &mut pinned| pinned != cell);
+        self.assignment.unregister(BackendId(cell.0));
+        self.migrations(&tracked, &before)
+    }
+
+    /// Pins `tenant` to `cell`, migrating it there from wherever it lives now.
+    pub fn pin(&mut self, tenant: TenantId, cell: CellId) -> Migration {
+        assert!(self.cells.contains_key(&cell), "{cell:?} is not a cell");
+        let mut tracked = self.tracked();
+        tracked.insert(tenant);
+        let before = self.placements(&tracked);
+        self.pinned.insert(tenant, cell);
+        self.migration(tenant, &before, &self.placements(&tracked))
+    }
+
+    /// Returns `tenant` to the cell the assignment picker gives it.
+    pub fn unpin(&mut self, tenant: TenantId) -> Migration {
+        let tracked = self.tracked();
+        let before = self.placements(&tracked);
+        self.pinned.remove(&tenant);
+        self.migration(tenant, &before, &self.placements(&tracked))
+    }
+
+    /// Reports `tenant`'s moves in the migrations that adding or retiring cells, pinning and unpinning return.
+    pub fn register_tenant(&mut self, tenant: TenantId) {
+        self.tenants.insert(tenant);
+    }
+
+    pub fn unregister_tenant(&mut self, tenant: TenantId) {
+        self.tenants.remove(&tenant);
+    }
+
+    /// Adds a backend to `cell`, moving it out of whichever cell it was in before.
+    pub fn register_in_cell(&mut self, id: BackendId, cell: CellId, health: Health, weight: u32) {
+        assert!(self.cells.contains_key(&cell), "{cell:?} is not a cell");
+        if self.backend_cells.get(&id).is_some_and(|&c| c != cell) {
+            self.unregister(id);
+        }
+        self.backend_cells.insert(id, cell);
+        let c = self.cells.get_mut(&cell).unwrap();
+        c.backends.insert(id);
+        c.picker.register_weighted(id, health, weight);
+    }
+
+    /// The cell `tenant` is routed to, if any cell has backends.
+    pub fn cell(&self, tenant: TenantId) -> Option<CellId> {
+        self.placement(tenant).map(|(cell, _)| cell)
+    }
+
+    /// The tenant's cell, and whether it had to skip cells without backends to get there.
+    fn placement(&self, tenant: TenantId) -> Option<(CellId, bool)> {
+        let mut order = self.pinned.get(&tenant).copied().into_iter().chain(
+            self.assignment
+                .shard(tenant)
+                .into_iter()
+                .map(|(c, _)| CellId(c.0)),
+        );
+        let primary = order.next()?;
+        std::iter::once(primary)
+            .chain(order)
+            .find(|c| self.cells.get(c).is_some_and(|c| !c.backends.is_empty()))
+            .map(|cell| (cell, cell != primary))
+    }
+
+    /// Registered and pinned tenants.
+    fn tracked(&self) -> BTreeSet<TenantId> {
+        self.tenants
+            .iter()
+            .chain(self.pinned.keys())
+            .copied()
+            .collect()
+    }
+
+    fn placements(&self, tenants: &BTreeSet<TenantId>) -> Placements {
+        tenants
+            .iter()
+            .map(|&t| {
+                let shard = self.shard(t).into_iter().map(|(b, _)| b).collect();
+                (t, (self.cell(t), shard))
+            })
+            .collect()
+    }
+
+    /// A migration for every tenant whose cell changed since `before`.
+    fn migrations(&self, tenants: &BTreeSet<TenantId>, before: &Placements) -> Vec<Migration> {
+        let after = self.placements(tenants);
+        tenants
+            .iter()
+            .filter(|t| before.get(t).map(|(cell, _)| *cell) != after.get(t).map(|(cell, _)| *cell))
+            .map(|&t| self.migration(t, before, &after))
+            .collect()
+    }
+
+    fn migration(&self, tenant: TenantId, before: &Placements, after: &Placements) -> Migration {
+        let (from, old) = before.get(&tenant).cloned().unwrap_or_default();
+        let (to, new) = after.get(&tenant).cloned().unwrap_or_default();
+        let shares = |a: &[BackendId], b: &[BackendId]| a.iter().any(|x| b.contains(x));
+        let exposed = after
+            .iter()
+            .filter(|&(&t, (_, theirs))| {
+                let theirs_before = before.get(&t).map_or(&[][..], |(_, s)| s);
+                t != tenant && shares(&new, theirs) && !shares(&old, theirs_before)
+            })
+            .map(|(&t, _)| t)
+            .collect();
+        Migration {
+            tenant,
+            from,
+            to,
+            before: old,
+            after: new,
+            exposed,
+        }
+    }
+}
+
+impl<P: Picker, A: Picker> Picker for CellRouter<P, A> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_cells(shard_size, DEFAULT_CELLS)
+    }
+
+    /// Backends registered without a cell keep the cell they already had, or join the cell with the fewest backends.
+    /// A router left without any cells, because every one was retired or none were opened, opens `CellId(0)` for them.
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        let cell = match self.backend_cells.get(&id) {
+            Some(&cell) => cell,
+            None => match self
+                .cells
+                .iter()
+                .min_by_key(|&(&cell, c)| (c.backends.len(), cell))
+            {
+                Some((&cell, _)) => cell,
+                None => {
+                    self.open(CellId(0));
+                    CellId(0)
+                }
+            },
+        };
+        self.register_in_cell(id, cell, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        let Some(cell) = self.backend_cells.remove(&id) else {
+            return;
+        };
+        if let Some(c) = self.cells.get_mut(&cell) {
+            c.backends.remove(&id);
+            c.picker.unregister(id);
+        }
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        let (cell, skipped) = self.placement(id).ok_or(PickError::NoBackends)?;
+        let pick = self.cells.get_mut(&cell).unwrap().picker.pick(id)?;
+        Ok(Pick::new(pick.backend, pick.fallback || skipped))
+    }
+
+    /// The tenant's shard within its cell.
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.placement(id)
+            .map(|(cell, _)| self.cells[&cell].picker.shard(id))
+            .unwrap_or_default()
+    }
+
+    /// Everything in a cell the tenant only reached by skipping empty ones, and fallbacks within its own cell.
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.placement(id).is_none_or(|(cell, skipped)| {
+            skipped || self.cells[&cell].picker.falls_back(id, backend)
+        })
+    }
+
+    fn report_load(&mut self, id: BackendId, load: f64) {
+        if let Some(c) = self
+            .backend_cells
+            .get(&id)
+            .and_then(|c| self.cells.get_mut(c))
+        {
+            c.picker.report_load(id, load);
+        }
+    }
+
+    fn on_request_complete(&mut self, id: BackendId) {
+        if let Some(c) = self
+            .backend_cells
+            .get(&id)
+            .and_then(|c| self.cells.get_mut(c))
+        {
+            c.picker.on_request_complete(id);
+        }
+    }
+
+    fn report_outcome(&mut self, tenant: TenantId, id: BackendId, outcome: Outcome) {
+        if let Some(c) = self
+            .backend_cells
+            .get(&id)
+            .and_then(|c| self.cells.get_mut(c))
+        {
+            c.picker.report_outcome(tenant, id, outcome);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Per-tenant circuit breaking: quarantining tenants whose requests keep taking backends down with them.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, SystemTime},
+};
+
+use crate::{BackendId, Health, Outcome, PickError, PickResult, Picker, TenantId};
+
+/// Distinct shard members that must fail a tenant's requests, with no success in between, to trip its breaker.
+pub const DEFAULT_FAILED_BACKENDS: usize = 3;
+/// How long a first quarantine lasts. Every time the same tenant trips again it lasts twice as long, up to
+/// `MAX_QUARANTINE_DOUBLINGS` doublings.
+pub const DEFAULT_QUARANTINE_TIME: Duration = Duration::from_secs(60);
+const MAX_QUARANTINE_DOUBLINGS: u64 = 6;
+
+#[derive(Debug, Default)]
+struct Breaker {
+    /// Backends that failed this tenant's requests since its last success.
+    failed: BTreeSet<BackendId>,
+    trips: u64,
+    /// When a quarantined tenant is let back in.
+    quarantined_until: Option<SystemTime>,
+    /// Set when the tenant has just come out of quarantine. Its next failure trips the breaker again straight away.
+    probation: bool,
+}
+
+/// Quarantines tenants that look like poison pills, in front of any `Picker`.
+///
+/// Shuffle sharding limits a poison-pill tenant to its own shard, but it keeps taking those backends down every
+/// time they come back. The breaker watches the outcomes callers report through `report_outcome`, each of which
+/// names the tenant whose request it was. Once a tenant's requests have failed on several different backends in a
+/// row, its picks fail with `PickError::TenantThrottled` until its quarantine runs out, after which a single failure
+/// sends it straight back. A failure on just one backend says nothing about the tenant, so it takes at least two.
+///
+/// Quarantines run out by the wall clock, so how long a tenant stays out does not depend on how much traffic other
+/// tenants send; `with_clock` replaces it, e.g. with a simulation's own.
+pub struct CircuitBreaker<P> {
+    inner: P,
+    breakers: BTreeMap<TenantId, Breaker>,
+    failed_backends: usize,
+    quarantine_time: Duration,
+    clock: Box<dyn Fn() -> SystemTime>,
+}
+
+impl<P: Picker> CircuitBreaker<P> {
+    /// Guards the tenants of `inner`.
+    pub fn with_picker(inner: P) -> Self {
+        Self {
+            inner,
+            breakers: BTreeMap::new(),
+            failed_backends: DEFAULT_FAILED_BACKENDS,
+            quarantine_time: DEFAULT_QUARANTINE_TIME,
+            clock: Box::new(SystemTime::now),
+        }
+    }
+
+    /// Trips a tenant's breaker once this many distinct backends fail its requests in a row. Tenants with smaller
+    /// shards trip once every member has failed, but never on a single backend.
+    pub fn with_failed_backends(mut self, backends: usize) -> Self {
+        assert!(
+            backends >= 2,
+            "one failing backend can't tell a poison pill from a bad backend"
+        );
+        self.failed_backends = backends;
+        self
+    }
+
+    /// Sets how long a first quarantine lasts.
+    pub fn with_quarantine_time(mut self, time: Duration) -> Self {
+        self.quarantine_time = time;
+        self
+    }
+
+    /// Replaces the wall clock that quarantines run out by.
+    pub fn with_clock(mut self, clock: impl Fn() -> SystemTime + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Tenants that are currently quarantined.
+    pub fn quarantined(&self) -> Vec<TenantId> {
+        let now = (self.clock)();
+        self.breakers
+            .iter()
+            .filter(|(_, b)| b.quarantined_until.is_some_and(|until| until > now))
+            .map(|(&t, _)| t)
+            .collect()
+    }
+
+    fn trip(&mut self, tenant: TenantId) {
+        let breaker = self.breakers.entry(tenant).or_default();
+        breaker.trips += 1;
+        let doublings = (breaker.trips - 1).min(MAX_QUARANTINE_DOUBLINGS);
+        breaker.quarantined_until = Some((self.clock)() + self.quarantine_time * (1 << doublings));
+        breaker.failed.clear();
+        breaker.probation = false;
+    }
+}
+
+impl<P: Picker> Picker for CircuitBreaker<P> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_picker(P::new(shard_size))
+    }
+
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.inner.register_weighted(id, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.inner.unregister(id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if let Some(breaker) = self.breakers.get_mut(&id) {
+            match breaker.quarantined_until {
+                Some(until) if until > (self.clock)() => return Err(PickError::TenantThrottled),
+                Some(_) => {
+                    breaker.quarantined_until = None;
+                    breaker.probation = true;
+                }
+                None => {}
+            }
+        }
+        self.inner.pick(id)
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.inner.shard(id)
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.inner.falls_back(id, backend)
+    }
+
+    fn report_load(&mut self, id: BackendId, load: f64) {
+        self.inner.report_load(id, load);
+    }
+
+    fn on_request_complete(&mut self, id: BackendId) {
+        self.inner.on_request_complete(id);
+    }
+
+    fn report_outcome(&mut self, tenant: TenantId, id: BackendId, outcome: Outcome) {
+        self.inner.report_outcome(tenant, id, outcome);
+        if outcome == Outcome::Success {
+            if let Some(breaker) = self.breakers.get_mut(&tenant) {
+                breaker.failed.clear();
+                breaker.probation = false;
+            }
+            return;
+        }
+        let breaker = self.breakers.entry(tenant).or_default();
+        breaker.failed.insert(id);
+        // Only a tenant with fewer shard members than `failed_backends` needs its shard looked up.
+        let failed = breaker.failed.len();
+        if breaker.probation
+            || failed >= self.failed_backends
+            || failed >= self.inner.shard(tenant).len().max(2)
+        {
+            self.trip(tenant);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    cmp::Reverse,
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use arc_swap::ArcSwap;
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    rendezvous_score, Backend, BackendId, Health, Pick, PickError, PickResult, Picker, TenantId,
+    DEFAULT_WEIGHT,
+};
+
+/// The same placement as `RendevouzShuffle`, but safe to share between threads.
+///
+/// Picks read an immutable snapshot of the fleet and never take a lock. Registration copies the snapshot, applies
+/// the change, and publishes the result; concurrent writers retry instead of blocking each other.
+///
+/// Sharing one RNG between threads would need a lock, so each pick seeds a fresh `R` from the picker's seed and the
+/// number of picks so far. A single thread replays the same choices for the same seed.
+pub struct ConcurrentRendevouzShuffle<R = SmallRng, H = SipHash13> {
+    snapshot: ArcSwap<Vec<Backend>>,
+    shard_size: usize,
+    shard_seed: u64,
+    hasher: H,
+    pick_seed: u64,
+    picks: AtomicU64,
+    prng: PhantomData<fn() -> R>,
+}
+
+impl<R: Rng + SeedableRng, H: HashFn + Default> ConcurrentRendevouzShuffle<R, H> {
+    pub fn new(shard_size: usize) -> Self {
+        Self::with_rng(shard_size, R::seed_from_u64(42))
+    }
+
+    /// Like `new`, but the choices within each shard are seeded from `prng`.
+    pub fn with_rng(shard_size: usize, mut prng: R) -> Self {
+        Self {
+            snapshot: ArcSwap::from_pointee(Vec::new()),
+            shard_size: shard_size.max(1),
+            shard_seed: 0,
+            hasher: H::default(),
+            pick_seed: prng.gen(),
+            picks: AtomicU64::new(0),
+            prng: PhantomData,
+        }
+    }
+
+    /// Replaces the hash function that places backends, e.g. to use a keyed or seeded variant.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        let rehashed = self
+            .snapshot
+            .load()
+            .iter()
+            .map(|b| b.hashed_with(&hasher))
+            .collect();
+        self.snapshot = ArcSwap::from_pointee(rehashed);
+        self.hasher = hasher;
+        self
+    }
+
+    /// Mixes `shard_seed` into the tenant's rendezvous hash, exactly like `RendevouzShuffle::with_shard_seed`.
+    pub fn with_shard_seed(mut self, shard_seed: u64) -> Self {
+        self.shard_seed = shard_seed;
+        self
+    }
+
+    pub fn register(&self, id: BackendId, health: Health) {
+        self.register_weighted(id, health, DEFAULT_WEIGHT);
+    }
+
+    pub fn register_weighted(&self, id: BackendId, health: Health, weight: u32) {
+        self.snapshot.rcu(|backends| {
+            let mut backends = Vec::clone(backends);
+            if let Some(existing) = backends.iter_mut().find(|b| b.id == id) {
+                existing.health = health;
+                existing.weight = weight;
+            } else {
+                backends.push(Backend::new(id, health, weight).hashed_with(&self.hasher));
+            }
+            backends
+        });
+    }
+
+    pub fn unregister(&self, id: BackendId) {
+        self.snapshot.rcu(|backends| {
+            let mut backends = Vec::clone(backends);
+            backends.retain(|b| b.id != id);
+            backends
+        });
+    }
+
+    pub fn pick(&self, id: TenantId) -> PickResult {
+        let backends = self.snapshot.load();
+        if backends.is_empty() {
+            return Err(PickError::NoBackends);
+        }
+        let healthy: Vec<&Backend> = self
+            .shard_members(&backends, id)
+            .filter(|b| b.health == Health::Up)
+            .collect();
+        let pick = self.picks.fetch_add(1, Ordering::Relaxed);
+        let mut prng = R::seed_from_u64(self.pick_seed.wrapping_add(pick));
+        let choice = healthy
+            .choose(&mut prng)
+            .ok_or(PickError::ShardUnavailable)?;
+        Ok(Pick::new(
+            choice.id,
+            self.outside_primary_shard(&backends, id, choice.id),
+        ))
+    }
+
+    /// Whether routing `id` to `backend` would be a fallback, as `pick` would report it.
+    pub fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.outside_primary_shard(&self.snapshot.load(), id, backend)
+    }
+
+    pub fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        let backends = self.snapshot.load();
+        let mut shard: Vec<&Backend> = self.shard_members(&backends, id).collect();
+        let th = self.hasher.hash_u64(id.0) ^ self.shard_seed;
+        shard.sort_by_key(|b| Reverse(rendezvous_score(th, b)));
+        shard.into_iter().map(|b| (b.id, b.health)).collect()
+    }
+
+    /// As in `RendevouzShuffle`, a member is a fallback when it only made the shard because backends that outrank
+    /// it are draining.
+    fn outside_primary_shard(&self, backends: &[Backend], id: TenantId, b: BackendId) -> bool {
+        if backends.iter().all(|b| b.health != Health::Draining) {
+            return false;
+        }
+        let th = self.hasher.hash_u64(id.0) ^ self.shard_seed;
+        let score = |b: &Backend| rendezvous_score(th, b);
+        backends.iter().find(|other| other.id == b).is_none_or(|b| {
+            backends
+                .iter()
+                .filter(|other| score(other) > score(b))
+                .count()
+                >= self.shard_size
+        })
+    }
+
+    /// The snapshot is shared, so rather than reordering it in place we select over a list of indices. Draining
+    /// backends leave the shard entirely, as in `RendevouzShuffle`.
+    fn shard_members<'a>(
+        &self,
+        backends: &'a [Backend],
+        id: TenantId,
+    ) -> impl Iterator<Item = &'a Backend> {
+        let th = self.hasher.hash_u64(id.0) ^ self.shard_seed;
+        let mut ranked: Vec<(Reverse<u64>, usize)> = backends
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.health != Health::Draining)
+            .map(|(i, b)| (Reverse(rendezvous_score(th, b)), i))
+            .collect();
+        if self.shard_size < ranked.len() {
+            ranked.select_nth_unstable(self.shard_size);
+            ranked.truncate(self.shard_size);
+        }
+        ranked.into_iter().map(|(_, i)| &backends[i])
+    }
+}
+
+impl<R: Rng + SeedableRng, H: HashFn + Default> Picker for ConcurrentRendevouzShuffle<R, H> {
+    fn new(shard_size: usize) -> Self {
+        ConcurrentRendevouzShuffle::new(shard_size)
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        ConcurrentRendevouzShuffle::register_weighted(self, id, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        ConcurrentRendevouzShuffle::unregister(self, id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        ConcurrentRendevouzShuffle::pick(self, id)
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        ConcurrentRendevouzShuffle::shard(self, id)
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        ConcurrentRendevouzShuffle::falls_back(self, id, backend)
+    }
+}
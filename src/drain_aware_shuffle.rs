@@ -1,28 +1,93 @@
-use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
 
-use crate::{hash, Backend, BackendId, Health, Picker, TenantId};
-pub struct DrainAwareShuffle {
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    weighted_shuffle, Backend, BackendId, Health, Pick, PickError, PickResult, Picker, ShardSize,
+    TenantId,
+};
+pub struct DrainAwareShuffle<R = SmallRng, H = SipHash13> {
     backends: Vec<Backend>,
-    shard_size: usize,
-    prng: SmallRng,
+    shard_size: ShardSize,
+    shard_seed: u64,
+    hasher: H,
+    prng: R,
 }
-impl Picker for DrainAwareShuffle {
-    fn new(shard_size: usize) -> Self {
+impl<R: Rng, H: HashFn + Default> DrainAwareShuffle<R, H> {
+    /// Like `Picker::new`, but `prng` decides where in the shard each request starts looking for a healthy
+    /// backend.
+    pub fn with_rng(shard_size: usize, prng: R) -> Self {
         Self {
-            shard_size,
             backends: Vec::new(),
-            prng: SmallRng::seed_from_u64(42),
+            shard_size: ShardSize::Fixed(shard_size),
+            shard_seed: 0,
+            hasher: H::default(),
+            prng,
         }
     }
-    fn register(&mut self, id: BackendId, health: Health) {
+
+    /// Replaces the hash function that shuffles backends for each tenant, e.g. to use a keyed or seeded variant.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Seeds every tenant's shuffle with `shard_seed` as well as the tenant id, independently of `prng`.
+    pub fn with_shard_seed(mut self, shard_seed: u64) -> Self {
+        self.shard_seed = shard_seed;
+        self
+    }
+
+    /// Gives tenants shards of different sizes, each cut from the front of the tenant's shuffle.
+    pub fn with_shard_size(mut self, shard_size: ShardSize) -> Self {
+        self.shard_size = shard_size;
+        self
+    }
+
+    fn tenant_hash(&self, id: TenantId) -> u64 {
+        self.hasher.hash_u64(id.0) ^ self.shard_seed
+    }
+
+    fn shard_members(&self, id: TenantId) -> Vec<Backend> {
+        let all_backends: Vec<Backend> = self
+            .backends
+            .iter()
+            .filter(|b| b.health != Health::Draining)
+            .cloned()
+            .collect();
+        weighted_shuffle(
+            &all_backends,
+            &self.hasher,
+            self.tenant_hash(id),
+            self.shard_size.of(id),
+        )
+    }
+
+    /// Whether `b` only made it into the tenant's shard because members of the shard it would have with nothing
+    /// draining are draining now.
+    fn outside_primary_shard(&self, id: TenantId, b: BackendId) -> bool {
+        if self.backends.iter().all(|b| b.health != Health::Draining) {
+            return false;
+        }
+        !weighted_shuffle(
+            &self.backends,
+            &self.hasher,
+            self.tenant_hash(id),
+            self.shard_size.of(id),
+        )
+        .iter()
+        .any(|primary| primary.id == b)
+    }
+}
+impl<R: Rng + SeedableRng, H: HashFn + Default> Picker for DrainAwareShuffle<R, H> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_rng(shard_size, R::seed_from_u64(42))
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
         if let Some(existing) = self.backends.iter_mut().find(|b| b.id == id) {
             existing.health = health;
+            existing.weight = weight;
         } else {
-            self.backends.push(Backend {
-                id,
-                health,
-                hash: hash(id),
-            });
+            self.backends.push(Backend::new(id, health, weight));
             self.backends.sort();
         }
     }
@@ -31,29 +96,34 @@ impl Picker for DrainAwareShuffle {
         self.backends.retain(|b| b.id != id);
     }
 
-    fn pick(&mut self, id: TenantId) -> Option<BackendId> {
+    fn pick(&mut self, id: TenantId) -> PickResult {
         if self.backends.is_empty() {
-            return None;
+            return Err(PickError::NoBackends);
         }
-        let mut all_backends: Vec<Backend> = self
-            .backends
-            .iter()
-            .filter(|b| b.health != Health::Draining)
-            .cloned()
-            .collect();
-        let (shuffled, _remainder) = {
-            let mut prng = SmallRng::seed_from_u64(id.0);
-            all_backends.partial_shuffle(&mut prng, self.shard_size)
-        };
-
-        // Note: different RNG! This one is not determinstic based on the tenant id.
-        let idx = self.prng.gen_range(0..self.shard_size);
-        for i in 0..self.shard_size {
+        let shuffled = self.shard_members(id);
+        if shuffled.is_empty() {
+            return Err(PickError::ShardUnavailable);
+        }
+
+        // Unlike the shard, which member serves the request is random.
+        let idx = self.prng.gen_range(0..shuffled.len());
+        for i in 0..shuffled.len() {
             let b = shuffled[(idx + i) % shuffled.len()];
             if b.health == Health::Up {
-                return Some(b.id);
+                return Ok(Pick::new(b.id, self.outside_primary_shard(id, b.id)));
             }
         }
-        None
+        Err(PickError::ShardUnavailable)
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.shard_members(id)
+            .into_iter()
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.outside_primary_shard(id, backend)
     }
 }
@@ -0,0 +1,82 @@
+//! Hash functions with pinned output.
+//!
+//! `std::collections::hash_map::DefaultHasher` makes no promise that its algorithm survives a toolchain upgrade,
+//! and every shard assignment is derived from hashes, so pickers take a `HashFn` instead. Keys are encoded
+//! explicitly (little-endian `u64`s, concatenated) rather than through `std::hash::Hash`, so that a service written
+//! in another language can reproduce the same placements.
+
+use std::hash::Hasher;
+
+use siphasher::sip::SipHasher13;
+
+pub trait HashFn {
+    fn hash_bytes(&self, bytes: &[u8]) -> u64;
+
+    fn hash_u64(&self, x: u64) -> u64 {
+        self.hash_bytes(&x.to_le_bytes())
+    }
+
+    fn hash_pair(&self, a: u64, b: u64) -> u64 {
+        let mut buf = [0; 16];
+        buf[..8].copy_from_slice(&a.to_le_bytes());
+        buf[8..].copy_from_slice(&b.to_le_bytes());
+        self.hash_bytes(&buf)
+    }
+}
+
+/// SipHash 1-3 with fixed keys. With the default (zero) keys this is exactly what `DefaultHasher::new()` computed
+/// as of 2024-01, so `Rendevouz` and equal-weight `RendevouzShuffle` place tenants as they did before pickers were
+/// generic over `HashFn`.
+///
+/// `NaiveShuffle`, `DrainAwareShuffle` and `BlockPicker` used to draw shards from a `SmallRng` seeded with the tenant
+/// id, whose output nothing pins. Moving them onto hashes moved every one of their tenants once, when upgrading past
+/// that change; their placements are stable from then on.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SipHash13 {
+    k0: u64,
+    k1: u64,
+}
+impl SipHash13 {
+    pub fn with_keys(k0: u64, k1: u64) -> Self {
+        Self { k0, k1 }
+    }
+}
+impl HashFn for SipHash13 {
+    fn hash_bytes(&self, bytes: &[u8]) -> u64 {
+        let mut h = SipHasher13::new_with_keys(self.k0, self.k1);
+        h.write(bytes);
+        h.finish()
+    }
+}
+
+/// XXH3 (64-bit). Much faster than SipHash on short keys.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct XxHash3 {
+    seed: u64,
+}
+impl XxHash3 {
+    pub fn with_seed(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+impl HashFn for XxHash3 {
+    fn hash_bytes(&self, bytes: &[u8]) -> u64 {
+        xxhash_rust::xxh3::xxh3_64_with_seed(bytes, self.seed)
+    }
+}
+
+/// wyhash, as implemented by the `wyhash` crate (0.5), which tracks a fixed revision of the reference code.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WyHash {
+    seed: u64,
+}
+impl WyHash {
+    pub fn with_seed(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+impl HashFn for WyHash {
+    fn hash_bytes(&self, bytes: &[u8]) -> u64 {
+        wyhash::wyhash(bytes, self.seed)
+    }
+}
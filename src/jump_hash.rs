@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    Backend, BackendId, Health, Pick, PickError, PickResult, Picker, TenantId,
+};
+
+/// Jump consistent hash (Lamping & Veach): maps `key` to a bucket in `0..buckets`, and growing `buckets` by one
+/// only moves the keys that land in the new bucket.
+fn jump(mut key: u64, buckets: usize) -> usize {
+    let (mut b, mut j) = (-1i64, 0i64);
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as usize
+}
+
+/// Jump hashing can only add or remove buckets at the end, so backends live in numbered slots that outlast them.
+///
+/// A backend occupies one slot per unit of weight. Unregistering it leaves its slots empty (keys that land there
+/// rehash), new backends fill the lowest empty slots first, and only empty slots at the end are dropped. Removing a
+/// backend from the middle of the fleet therefore moves nobody but its own tenants.
+#[derive(Default)]
+struct Slots {
+    backends: BTreeMap<BackendId, Backend>,
+    slots: Vec<Option<BackendId>>,
+}
+
+impl Slots {
+    fn register(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.backends
+            .entry(id)
+            .and_modify(|b| {
+                b.health = health;
+                b.weight = weight;
+            })
+            .or_insert_with(|| Backend::new(id, health, weight));
+
+        let mut held = self.slots.iter().filter(|&&s| s == Some(id)).count();
+        for slot in self.slots.iter_mut().rev() {
+            if held > weight as usize && *slot == Some(id) {
+                *slot = None;
+                held -= 1;
+            }
+        }
+        for slot in self.slots.iter_mut() {
+            if held < weight as usize && slot.is_none() {
+                *slot = Some(id);
+                held += 1;
+            }
+        }
+        while held < weight as usize {
+            self.slots.push(Some(id));
+            held += 1;
+        }
+        self.trim();
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.backends.remove(&id);
+        for slot in self.slots.iter_mut().filter(|s| **s == Some(id)) {
+            *slot = None;
+        }
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.slots.last() == Some(&None) {
+            self.slots.pop();
+        }
+    }
+
+    /// The backend in the tenant's `probe`th slot, if that slot is occupied.
+    fn probe<H: HashFn>(&self, hasher: &H, id: TenantId, probe: u64) -> Option<Backend> {
+        let slot = jump(hasher.hash_pair(id.0, probe), self.slots.len());
+        self.slots[slot].map(|b| self.backends[&b])
+    }
+
+    /// Distinct backends accepted by `eligible`, in the order the tenant's probes find them, stopping after
+    /// `limit`.
+    fn probe_order<H: HashFn>(
+        &self,
+        hasher: &H,
+        id: TenantId,
+        limit: usize,
+        eligible: impl Fn(&Backend) -> bool,
+    ) -> Vec<Backend> {
+        let limit = limit.min(self.backends.values().filter(|b| eligible(b)).count());
+        let mut found: Vec<Backend> = Vec::with_capacity(limit);
+        let mut probe = 0;
+        while found.len() < limit {
+            if let Some(b) = self.probe(hasher, id, probe) {
+                if eligible(&b) && !found.iter().any(|f| f.id == b.id) {
+                    found.push(b);
+                }
+            }
+            probe += 1;
+        }
+        found
+    }
+}
+
+/// Jump consistent hashing. A tenant probes slots until it finds a healthy backend, so an unhealthy backend's
+/// tenants spread evenly over the rest of the fleet.
+pub struct JumpHash<H = SipHash13> {
+    slots: Slots,
+    hasher: H,
+}
+
+impl<H: HashFn> JumpHash<H> {
+    /// Replaces the hash function, e.g. to use a keyed or seeded variant.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.hasher = hasher;
+        self
+    }
+}
+
+impl<H: HashFn + Default> Picker for JumpHash<H> {
+    fn new(_shard_size: usize) -> Self {
+        Self {
+            slots: Slots::default(),
+            hasher: H::default(),
+        }
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.slots.register(id, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.slots.unregister(id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if self.slots.backends.is_empty() {
+            return Err(PickError::NoBackends);
+        }
+        if !self.slots.backends.values().any(|b| b.health == Health::Up) {
+            return Err(PickError::ShardUnavailable);
+        }
+        let mut probes = (0..).filter_map(|probe| self.slots.probe(&self.hasher, id, probe));
+        let first = probes.next().unwrap();
+        if first.health == Health::Up {
+            return Ok(Pick::primary(first.id));
+        }
+        let choice = probes.find(|b| b.health == Health::Up).unwrap();
+        Ok(Pick::new(choice.id, true))
+    }
+
+    /// The whole fleet, in the order the tenant's probes reach it.
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.slots
+            .probe_order(&self.hasher, id, usize::MAX, |_| true)
+            .into_iter()
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    /// Anything but the backend the tenant's first successful probe finds.
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        !self.slots.backends.is_empty()
+            && (0..)
+                .find_map(|probe| self.slots.probe(&self.hasher, id, probe))
+                .is_some_and(|first| first.id != backend)
+    }
+}
+
+/// Shuffle sharding with jump hashing: a tenant's shard is the first `shard_size` distinct backends its probes
+/// find. Draining backends are skipped while building the shard; down backends are not, so a poison-pill tenant
+/// stays inside its shard.
+pub struct JumpShuffle<R = SmallRng, H = SipHash13> {
+    slots: Slots,
+    shard_size: usize,
+    hasher: H,
+    prng: R,
+}
+
+impl<R: Rng, H: HashFn + Default> JumpShuffle<R, H> {
+    /// Like `Picker::new`, but `prng` chooses among the healthy members of the shard.
+    pub fn with_rng(shard_size: usize, prng: R) -> Self {
+        Self {
+            slots: Slots::default(),
+            shard_size: shard_size.max(1),
+            hasher: H::default(),
+            prng,
+        }
+    }
+
+    /// Replaces the hash function that places tenants.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    fn shard_members(&self, id: TenantId) -> Vec<Backend> {
+        self.slots
+            .probe_order(&self.hasher, id, self.shard_size, |b| {
+                b.health != Health::Draining
+            })
+    }
+
+    /// Whether `b` is in the tenant's shard only because earlier probes found draining backends and moved on.
+    fn outside_primary_shard(&self, id: TenantId, b: BackendId) -> bool {
+        self.slots
+            .backends
+            .values()
+            .any(|b| b.health == Health::Draining)
+            && !self
+                .slots
+                .probe_order(&self.hasher, id, self.shard_size, |_| true)
+                .iter()
+                .any(|primary| primary.id == b)
+    }
+}
+
+impl<R: Rng + SeedableRng, H: HashFn + Default> Picker for JumpShuffle<R, H> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_rng(shard_size, R::seed_from_u64(42))
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.slots.register(id, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.slots.unregister(id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if self.slots.backends.is_empty() {
+            return Err(PickError::NoBackends);
+        }
+        let healthy: Vec<Backend> = self
+            .shard_members(id)
+            .into_iter()
+            .filter(|b| b.health == Health::Up)
+            .collect();
+        let choice = healthy
+            .choose(&mut self.prng)
+            .ok_or(PickError::ShardUnavailable)?;
+        Ok(Pick::new(
+            choice.id,
+            self.outside_primary_shard(id, choice.id),
+        ))
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.shard_members(id)
+            .into_iter()
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.outside_primary_shard(id, backend)
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{BackendId, Health, Outcome, Pick, PickResult, Picker, TenantId};
+
+/// How quickly reported loads replace older ones.
+const EWMA_ALPHA: f64 = 0.3;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Load {
+    outstanding: u64,
+    ewma: Option<f64>,
+}
+impl Load {
+    /// Expected wait for one more request: everything in flight, plus it, at the recent pace. Backends that have
+    /// never had a load reported count as a load of 1.
+    fn cost(&self) -> f64 {
+        (self.outstanding + 1) as f64 * self.ewma.unwrap_or(1.0)
+    }
+}
+
+/// Power-of-two-choices within the shard of any `Picker`.
+///
+/// Every pick draws two distinct healthy members of the inner picker's shard and routes to the one with the lower
+/// cost, where cost is the number of outstanding requests scaled by an EWMA of whatever the caller passes to
+/// `report_load` (latency, typically). A pick counts as outstanding until the caller calls `on_request_complete`.
+///
+/// The inner picker only picks when its shard has no healthy member, so that its error carries through; otherwise it
+/// is asked `falls_back`. Pickers that count or act on their own picks, such as `BoundedLoadRendezvous`,
+/// `CircuitBreaker` or `OutlierDetection`, therefore see none of `LeastLoaded`'s, and the wrappers among them belong
+/// outside it. A picker whose shard ranks the whole fleet, such as `Rendevouz`, is balanced over the whole fleet,
+/// and every pick but its top choice is a fallback.
+pub struct LeastLoaded<P, R = SmallRng> {
+    inner: P,
+    loads: BTreeMap<BackendId, Load>,
+    prng: R,
+}
+
+impl<P: Picker, R: Rng> LeastLoaded<P, R> {
+    /// Balances within the shards of `inner`, using `prng` to break ties between equally loaded candidates.
+    pub fn with_rng(inner: P, prng: R) -> Self {
+        Self {
+            inner,
+            loads: BTreeMap::new(),
+            prng,
+        }
+    }
+}
+
+impl<P: Picker, R: Rng + SeedableRng> Picker for LeastLoaded<P, R> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_rng(P::new(shard_size), R::seed_from_u64(42))
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.inner.register_weighted(id, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.inner.unregister(id);
+        self.loads.remove(&id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        let healthy: Vec<BackendId> = self
+            .inner
+            .shard(id)
+            .into_iter()
+            .filter(|&(_, health)| health == Health::Up)
+            .map(|(b, _)| b)
+            .collect();
+        if healthy.is_empty() {
+            let pick = self.inner.pick(id)?;
+            self.loads.entry(pick.backend).or_default().outstanding += 1;
+            return Ok(pick);
+        }
+        let candidates: Vec<BackendId> = healthy
+            .choose_multiple(&mut self.prng, 2)
+            .copied()
+            .collect();
+        let (a, b) = (candidates[0], candidates[candidates.len() - 1]);
+        let cost = |b: BackendId| self.loads.get(&b).copied().unwrap_or_default().cost();
+        let (cost_a, cost_b) = (cost(a), cost(b));
+        let choice = if cost_a < cost_b {
+            a
+        } else if cost_b < cost_a || self.prng.gen() {
+            b
+        } else {
+            a
+        };
+        self.loads.entry(choice).or_default().outstanding += 1;
+        Ok(Pick::new(choice, self.inner.falls_back(id, choice)))
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.inner.shard(id)
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.inner.falls_back(id, backend)
+    }
+
+    fn report_load(&mut self, id: BackendId, load: f64) {
+        self.inner.report_load(id, load);
+        let l = self.loads.entry(id).or_default();
+        l.ewma = Some(match l.ewma {
+            Some(ewma) => EWMA_ALPHA * load + (1.0 - EWMA_ALPHA) * ewma,
+            None => load,
+        });
+    }
+
+    fn on_request_complete(&mut self, id: BackendId) {
+        self.inner.on_request_complete(id);
+        if let Some(l) = self.loads.get_mut(&id) {
+            l.outstanding = l.outstanding.saturating_sub(1);
+        }
+    }
+
+    fn report_outcome(&mut self, tenant: TenantId, id: BackendId, outcome: Outcome) {
+        self.inner.report_outcome(tenant, id, outcome);
+    }
+}
@@ -1,4 +1,7 @@
-use std::{collections::hash_map::DefaultHasher, hash::Hasher, ops::BitXor};
+use std::{cmp::Reverse, collections::BTreeMap, ops::BitXor};
+
+use hash_fn::{HashFn, SipHash13};
+use rand::Rng;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
 pub struct TenantId(pub u64);
@@ -6,6 +9,10 @@ pub struct TenantId(pub u64);
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub struct BackendId(pub u64);
 
+/// A failure domain, such as an availability zone.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct ZoneId(pub u64);
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub enum Health {
     Up,
@@ -13,68 +20,235 @@ pub enum Health {
     Down,
 }
 
+/// How a request routed to a backend turned out.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum Outcome {
+    Success,
+    Error,
+    Timeout,
+}
+
+/// Where a picker routed a request.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Pick {
+    pub backend: BackendId,
+    /// The backend lies outside the tenant's primary shard, the backends it would use if none were draining or
+    /// down, because members of that shard are unavailable.
+    pub fallback: bool,
+}
+impl Pick {
+    /// A backend from the tenant's primary shard.
+    pub fn primary(backend: BackendId) -> Self {
+        Self {
+            backend,
+            fallback: false,
+        }
+    }
+
+    pub fn new(backend: BackendId, fallback: bool) -> Self {
+        Self { backend, fallback }
+    }
+}
+
+/// Why a picker could not route a request.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PickError {
+    /// No backends are registered at all.
+    NoBackends,
+    /// Backends are registered, but none the tenant may use is up.
+    ShardUnavailable,
+    /// The picker is refusing to route this tenant for the time being.
+    TenantThrottled,
+}
+impl std::fmt::Display for PickError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PickError::NoBackends => write!(f, "no backends are registered"),
+            PickError::ShardUnavailable => write!(f, "no backend in the shard is up"),
+            PickError::TenantThrottled => write!(f, "the tenant is throttled"),
+        }
+    }
+}
+impl std::error::Error for PickError {}
+
+pub type PickResult = Result<Pick, PickError>;
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub struct Backend {
     id: BackendId,
     health: Health,
     hash: u64,
+    weight: u32,
+}
+impl Backend {
+    pub(crate) fn new(id: BackendId, health: Health, weight: u32) -> Self {
+        assert!(weight > 0, "{id:?} must have a positive weight");
+        Self {
+            id,
+            health,
+            hash: SipHash13::default().hash_u64(id.0),
+            weight,
+        }
+    }
+
+    /// Re-derives the backend's hash with `h`, for pickers that place backends by hash.
+    pub(crate) fn hashed_with<H: HashFn>(mut self, h: &H) -> Self {
+        self.hash = h.hash_u64(self.id.0);
+        self
+    }
+}
+
+/// The weight given to backends registered without an explicit capacity.
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+/// How many backends each tenant's shard holds, for pickers that let it differ between tenants.
+///
+/// Those pickers rank every backend for a tenant the same way whatever its shard size, and the shard is a prefix of
+/// that ranking. Growing a tenant's shard only ever adds backends to it, and shrinking it only removes some.
+pub enum ShardSize {
+    /// The same size for every tenant.
+    Fixed(usize),
+    /// Tenants listed in `tenants` get their own size, and the rest `default`.
+    Tiers {
+        default: usize,
+        tenants: BTreeMap<TenantId, usize>,
+    },
+    /// Whatever the callback says.
+    PerTenant(Box<dyn Fn(TenantId) -> usize>),
+}
+impl ShardSize {
+    /// The size of `id`'s shard. A size of 0 counts as 1, so that no tenant is left without a backend while the
+    /// fleet has some up.
+    pub fn of(&self, id: TenantId) -> usize {
+        let k = match self {
+            ShardSize::Fixed(k) => *k,
+            ShardSize::Tiers { default, tenants } => tenants.get(&id).copied().unwrap_or(*default),
+            ShardSize::PerTenant(f) => f(id),
+        };
+        k.max(1)
+    }
 }
 
+/// Routes tenants to backends. The trait is object safe, so pickers can be chosen at runtime (see `registry`).
+///
+/// Every picker degrades the same way when the fleet is small or unhealthy:
+///
+/// - With no backends registered, `pick` fails with `PickError::NoBackends` and every shard is empty.
+/// - A shard size of 0 counts as 1.
+/// - A fleet with fewer backends than the shard size, such as a new region bootstrapping with two or three, gives
+///   every tenant the whole fleet as its shard and keeps serving.
+/// - New requests only ever go to backends that are up. When backends are registered but none the tenant may use
+///   is up, because they are all down or all draining, `pick` fails with `PickError::ShardUnavailable`.
 pub trait Picker {
-    fn new(shard_size: usize) -> Self;
-    fn register(&mut self, id: BackendId, health: Health);
+    fn new(shard_size: usize) -> Self
+    where
+        Self: Sized;
+    fn register(&mut self, id: BackendId, health: Health) {
+        self.register_weighted(id, health, DEFAULT_WEIGHT);
+    }
+    /// Registers a backend with a relative capacity. A backend with weight 2 should receive
+    /// twice the traffic of a backend with weight 1.
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32);
     fn unregister(&mut self, id: BackendId);
-    fn pick(&mut self, id: TenantId) -> Option<BackendId>;
+    /// Chooses a backend for one of the tenant's requests, or says why there is none.
+    fn pick(&mut self, id: TenantId) -> PickResult;
+    /// The backends that `id` may be routed to, in the tenant's order of preference, along with their health.
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)>;
+    /// Whether routing one of `id`'s requests to `backend` would be a fallback, as `Pick::fallback` reports it,
+    /// without picking anything. Pickers that never fall back keep the default.
+    fn falls_back(&self, _id: TenantId, _backend: BackendId) -> bool {
+        false
+    }
+    /// Reports a load signal for a backend, such as a request's latency. Pickers that don't balance on load
+    /// ignore it.
+    fn report_load(&mut self, _id: BackendId, _load: f64) {}
+    /// Reports that a request previously routed to `id` has finished.
+    fn on_request_complete(&mut self, _id: BackendId) {}
+    /// Reports how one of `tenant`'s requests, previously routed to `id`, turned out. Pickers that don't track
+    /// errors ignore it.
+    fn report_outcome(&mut self, _tenant: TenantId, _id: BackendId, _outcome: Outcome) {}
 }
 
+/// Smooth weighted round-robin (as in nginx). With equal weights this is plain round-robin.
 pub struct RoundRobin {
-    idx: usize,
     backends: Vec<Backend>,
+    current: Vec<i64>,
 }
 impl Picker for RoundRobin {
     fn new(_shard_size: usize) -> Self {
         Self {
             backends: Vec::new(),
-            idx: 0,
+            current: Vec::new(),
         }
     }
-    fn register(&mut self, id: BackendId, health: Health) {
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
         if let Some(existing) = self.backends.iter_mut().find(|b| b.id == id) {
             existing.health = health;
+            existing.weight = weight;
         } else {
-            self.backends.push(Backend {
-                id,
-                health,
-                hash: hash(id),
-            });
+            self.backends.push(Backend::new(id, health, weight));
+            self.current.push(0);
         }
     }
 
     fn unregister(&mut self, id: BackendId) {
-        self.backends.retain(|b| b.id != id);
+        if let Some(idx) = self.backends.iter().position(|b| b.id == id) {
+            self.backends.remove(idx);
+            self.current.remove(idx);
+        }
     }
 
-    fn pick(&mut self, _id: TenantId) -> Option<BackendId> {
+    fn pick(&mut self, _id: TenantId) -> PickResult {
         if self.backends.is_empty() {
-            return None;
+            return Err(PickError::NoBackends);
         }
-        for _ in 0..self.backends.len() {
-            self.idx = (self.idx + 1) % self.backends.len();
-            let b = self.backends[self.idx];
+        // Every healthy backend earns its weight, the richest one is chosen and pays back the total.
+        let mut total = 0;
+        let mut best: Option<usize> = None;
+        for (i, b) in self.backends.iter().enumerate() {
             if b.health != Health::Up {
                 continue;
             }
-            return Some(b.id);
+            self.current[i] += b.weight as i64;
+            total += b.weight as i64;
+            if best.is_none_or(|j| self.current[i] > self.current[j]) {
+                best = Some(i);
+            }
         }
-        None
+        let best = best.ok_or(PickError::ShardUnavailable)?;
+        self.current[best] -= total;
+        Ok(Pick::primary(self.backends[best].id))
+    }
+
+    /// Every tenant shares the whole fleet.
+    fn shard(&self, _id: TenantId) -> Vec<(BackendId, Health)> {
+        self.backends.iter().map(|b| (b.id, b.health)).collect()
     }
 }
 
 pub mod block_picker;
+pub mod bounded_load;
+pub mod cell;
+pub mod circuit_breaker;
+pub mod concurrent;
 pub mod drain_aware_shuffle;
+pub mod hash_fn;
+pub mod jump_hash;
+pub mod least_loaded;
+pub mod metrics;
+pub mod maglev;
+pub mod multi_probe;
 pub mod naive_shuffle;
+pub mod outlier;
+pub mod overrides;
+pub mod registry;
+pub mod report;
 pub mod rendevouz;
 pub mod rendevouz_shuffle;
+pub mod ring;
+pub mod scenario;
+pub mod simulator;
+pub mod zoned_shuffle;
 
 /// Taken from FxHash, this is a mediocre quality (but extremely fast!) way to
 /// combine two hash values.
@@ -83,9 +257,102 @@ pub(crate) fn combine(a: u64, b: u64) -> u64 {
     a.rotate_left(5).bitxor(b).wrapping_mul(K)
 }
 
-/// This uses the hash_map default (should be SipHash 1-3 as of 2024-01).
-pub(crate) fn hash<T: std::hash::Hash>(t: T) -> u64 {
-    let mut h = DefaultHasher::new();
-    t.hash(&mut h);
-    h.finish()
+/// Weighted rendezvous scoring (Schindelhauer & Schomaker's logarithmic method). `hash` is mapped onto the open
+/// interval (0, 1) and the score is `-weight / ln(u)`, so the highest-scoring backend wins with probability
+/// proportional to its weight, and raising one backend's weight only steals keys for that backend.
+///
+/// The score is always positive, so its bit pattern is returned directly as an order-preserving key.
+pub(crate) fn weighted_score(hash: u64, weight: u32) -> u64 {
+    // The top 53 bits convert to f64 exactly; the half-step offset keeps us away from both 0 and 1.
+    let u = ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    (-(weight as f64) / u.ln()).to_bits()
+}
+
+/// The weighted rendezvous score that `RendevouzShuffle` ranks backends by for the tenant hash `th`. Before backends
+/// had weights, a shard was the backends with the lowest `combine(th, b.hash)`; scoring its complement keeps every
+/// equal-weight shard where it was.
+pub(crate) fn rendezvous_score(th: u64, b: &Backend) -> u64 {
+    weighted_score(!combine(th, b.hash), b.weight)
+}
+
+/// A weighted shuffle (Efraimidis & Spirakis): every backend gets a key scaled by its weight, and the `amount`
+/// highest keys are returned in descending order. With equal weights this is a uniform shuffle.
+///
+/// Membership drawn this way is already weighted, so pickers choose uniformly among a shard's members, which keeps
+/// every backend's share of traffic in line with its weight. Weighting that choice too would count capacity twice.
+///
+/// The keys hash the tenant's hash `th` with each backend's position in `backends`, rather than coming from an RNG
+/// whose output may change between versions and platforms. Keying on position keeps the shuffle naive: a backend
+/// joining or leaving the list moves the keys of every backend after it.
+pub(crate) fn weighted_shuffle<H: HashFn>(
+    backends: &[Backend],
+    hasher: &H,
+    th: u64,
+    amount: usize,
+) -> Vec<Backend> {
+    let mut keyed: Vec<(u64, Backend)> = backends
+        .iter()
+        .zip(0..)
+        .map(|(&b, i)| (weighted_score(hasher.hash_pair(th, i), b.weight), b))
+        .collect();
+    // Only the top `amount` need sorting, so a shard costs O(n + k log k) rather than O(n log n).
+    if amount < keyed.len() {
+        keyed.select_nth_unstable_by_key(amount, |&(key, _)| Reverse(key));
+        keyed.truncate(amount);
+    }
+    keyed.sort_unstable_by_key(|&(key, _)| Reverse(key));
+    keyed.into_iter().map(|(_, b)| b).collect()
+}
+
+/// Builds a shard that spans as many groups of backends, such as zones or blocks, as it has room for. The groups are
+/// ordered for the tenant by `combine(th, group_hash(group))` and their members by weighted rendezvous score, and the
+/// shard takes the best remaining member of each group in turn until it holds `shard_size`. Backends that `eligible`
+/// rejects are left out.
+pub(crate) fn spread_shard<G: Copy + Ord>(
+    backends: &[(G, Backend)],
+    th: u64,
+    group_hash: impl Fn(G) -> u64,
+    shard_size: usize,
+    eligible: impl Fn(&Backend) -> bool,
+) -> Vec<(G, Backend)> {
+    let mut groups: BTreeMap<G, Vec<Backend>> = BTreeMap::new();
+    for &(group, b) in backends {
+        if eligible(&b) {
+            groups.entry(group).or_default().push(b);
+        }
+    }
+    let mut groups: Vec<(G, Vec<Backend>)> = groups.into_iter().collect();
+    groups.sort_by_key(|&(group, _)| Reverse(combine(th, group_hash(group))));
+    for (_, members) in &mut groups {
+        members.sort_by_key(|b| Reverse(weighted_score(combine(th, b.hash), b.weight)));
+    }
+
+    let mut shard = Vec::with_capacity(shard_size);
+    for rank in 0.. {
+        let before = shard.len();
+        for (group, members) in &groups {
+            if shard.len() == shard_size {
+                return shard;
+            }
+            if let Some(&b) = members.get(rank) {
+                shard.push((*group, b));
+            }
+        }
+        if shard.len() == before {
+            break;
+        }
+    }
+    shard
+}
+
+/// Chooses an index into a non-empty `weights` with probability proportional to its weight.
+pub(crate) fn weighted_index<R: Rng>(weights: &[u64], prng: &mut R) -> usize {
+    let mut r = prng.gen_range(0..weights.iter().sum::<u64>());
+    for (i, &w) in weights.iter().enumerate() {
+        if r < w {
+            return i;
+        }
+        r -= w;
+    }
+    unreachable!("r is always less than the total weight")
 }
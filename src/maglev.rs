@@ -0,0 +1,386 @@
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    Backend, BackendId, Health, Pick, PickError, PickResult, Picker, TenantId,
+};
+
+/// The default lookup table size. It must be prime, and much larger than the fleet for an even spread.
+pub const DEFAULT_TABLE_SIZE: usize = 65_537;
+
+/// Seeds the hash that a backend's `skip` comes from, so that it is independent of the hash its `offset` comes from.
+const SKIP_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Fills a Maglev lookup table of `size` slots with indices into `backends`, skipping those that `eligible`
+/// rejects.
+///
+/// Every backend walks its own permutation of the slots, `(offset + j * skip) % size`, where `offset` and `skip` come
+/// from two independent hashes of its id, as in the paper. The backends take turns claiming their next free slot. A
+/// backend claims `weight` slots per turn, so its share of the table is proportional to its weight.
+fn populate<H: HashFn>(
+    hasher: &H,
+    backends: &[Backend],
+    size: usize,
+    eligible: impl Fn(&Backend) -> bool,
+) -> Vec<usize> {
+    let candidates: Vec<usize> = (0..backends.len())
+        .filter(|&i| eligible(&backends[i]))
+        .collect();
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+    let size = size as u64;
+    let permutation: Vec<(u64, u64)> = candidates
+        .iter()
+        .map(|&i| {
+            let b = &backends[i];
+            (
+                b.hash % size,
+                hasher.hash_pair(b.id.0, SKIP_SEED) % (size - 1) + 1,
+            )
+        })
+        .collect();
+
+    let mut table: Vec<Option<usize>> = vec![None; size as usize];
+    let mut next = vec![0u64; candidates.len()];
+    let mut filled = 0;
+    'fill: loop {
+        for (c, &i) in candidates.iter().enumerate() {
+            let (offset, skip) = permutation[c];
+            for _ in 0..backends[i].weight {
+                let mut slot = (offset + next[c] * skip) % size;
+                while table[slot as usize].is_some() {
+                    next[c] += 1;
+                    slot = (offset + next[c] * skip) % size;
+                }
+                table[slot as usize] = Some(i);
+                next[c] += 1;
+                filled += 1;
+                if filled == size {
+                    break 'fill;
+                }
+            }
+        }
+    }
+    table.into_iter().map(|i| i.unwrap()).collect()
+}
+
+/// The slot for a tenant's `probe`th lookup. Backends are placed by `hash_u64` of their id, so tenants hash a
+/// (tenant, probe) pair instead; otherwise tenant `n` would land on backend `n`'s first choice of slot.
+fn lookup<H: HashFn>(hasher: &H, id: TenantId, probe: u64, size: usize) -> usize {
+    (hasher.hash_pair(id.0, probe) % size as u64) as usize
+}
+
+/// Indices of the first `members` distinct backends that a tenant's lookups find, one lookup per member. A lookup that
+/// lands on a backend already found steps forward through the table to the next one that isn't.
+///
+/// A table smaller than the fleet leaves some backends without a slot, so fewer than `members` may come back: once a
+/// lookup has stepped through the whole table without finding anyone new, every owner has been found.
+fn probe_order<H: HashFn>(hasher: &H, table: &[usize], id: TenantId, members: usize) -> Vec<usize> {
+    let mut found: Vec<usize> = Vec::with_capacity(members);
+    for probe in 0..members as u64 {
+        let start = lookup(hasher, id, probe, table.len());
+        let Some(slot) = (0..table.len())
+            .map(|step| (start + step) % table.len())
+            .find(|&slot| !found.contains(&table[slot]))
+        else {
+            break;
+        };
+        found.push(table[slot]);
+    }
+    found
+}
+
+fn is_prime(n: usize) -> bool {
+    n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d))
+}
+
+/// Maglev consistent hashing (Eisenbud et al., NSDI '16).
+///
+/// A tenant hashes to one slot of a prime-sized lookup table, so `pick` is O(1). The table only holds healthy
+/// backends and is rebuilt whenever a backend is registered or unregistered. A tenant whose slot would hold a backend
+/// that isn't up is routed to whichever backend holds it instead, as a fallback.
+pub struct Maglev<H = SipHash13> {
+    backends: Vec<Backend>,
+    table: Vec<usize>,
+    /// The table as it would be with every backend up. A tenant whose slot holds someone else in `table` has
+    /// fallen back.
+    primary: Vec<usize>,
+    table_size: usize,
+    hasher: H,
+}
+
+impl<H: HashFn> Maglev<H> {
+    /// Sets the lookup table size, which must be prime. Backends beyond the first `table_size` to claim a slot get
+    /// none, and no tenant is routed to them.
+    pub fn with_table_size(mut self, table_size: usize) -> Self {
+        assert!(is_prime(table_size), "{table_size} is not prime");
+        self.table_size = table_size;
+        self.rebuild();
+        self
+    }
+
+    /// Replaces the hash function, e.g. to use a keyed or seeded variant.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.backends = self
+            .backends
+            .iter()
+            .map(|b| b.hashed_with(&hasher))
+            .collect();
+        self.hasher = hasher;
+        self.rebuild();
+        self
+    }
+
+    fn eligible(b: &Backend) -> bool {
+        b.health == Health::Up
+    }
+
+    /// Rebuilds both tables, after backends come, go or change weight.
+    fn rebuild(&mut self) {
+        self.primary = populate(&self.hasher, &self.backends, self.table_size, |_| true);
+        self.rebuild_eligible();
+    }
+
+    /// Rebuilds the table of eligible backends, after a health change.
+    fn rebuild_eligible(&mut self) {
+        self.table = if self.backends.iter().all(Self::eligible) {
+            self.primary.clone()
+        } else {
+            populate(
+                &self.hasher,
+                &self.backends,
+                self.table_size,
+                Self::eligible,
+            )
+        };
+    }
+}
+
+impl<H: HashFn + Default> Picker for Maglev<H> {
+    fn new(_shard_size: usize) -> Self {
+        Self {
+            backends: Vec::new(),
+            table: Vec::new(),
+            primary: Vec::new(),
+            table_size: DEFAULT_TABLE_SIZE,
+            hasher: H::default(),
+        }
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        if let Some(existing) = self.backends.iter_mut().find(|b| b.id == id) {
+            let before = *existing;
+            existing.health = health;
+            existing.weight = weight;
+            if before.weight == weight {
+                // Most health changes leave the table as it was, and rebuilding it isn't cheap.
+                if Self::eligible(&before) != Self::eligible(existing) {
+                    self.rebuild_eligible();
+                }
+                return;
+            }
+        } else {
+            self.backends
+                .push(Backend::new(id, health, weight).hashed_with(&self.hasher));
+            self.backends.sort();
+        }
+        self.rebuild();
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.backends.retain(|b| b.id != id);
+        self.rebuild();
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if self.backends.is_empty() {
+            return Err(PickError::NoBackends);
+        }
+        if self.table.is_empty() {
+            return Err(PickError::ShardUnavailable);
+        }
+        let slot = lookup(&self.hasher, id, 0, self.table.len());
+        Ok(Pick::new(
+            self.backends[self.table[slot]].id,
+            self.table[slot] != self.primary[slot],
+        ))
+    }
+
+    /// The whole fleet in the tenant's order of preference, as for `Rendevouz`: first the backend the tenant's slot
+    /// holds, then the rest of the table in the order further lookups find them, as `MaglevShuffle` builds its
+    /// shards. Backends that hold no slots, because they aren't up or the table is smaller than the fleet, come last.
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        let up = self.backends.iter().filter(|b| Self::eligible(b)).count();
+        let mut ranked = probe_order(&self.hasher, &self.table, id, up);
+        let mut slotless = vec![true; self.backends.len()];
+        for &i in &ranked {
+            slotless[i] = false;
+        }
+        ranked.extend((0..self.backends.len()).filter(|&i| slotless[i]));
+        ranked
+            .into_iter()
+            .map(|i| (self.backends[i].id, self.backends[i].health))
+            .collect()
+    }
+
+    /// Anything but the backend that holds the tenant's slot when every backend is up.
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        if self.primary.is_empty() {
+            return false;
+        }
+        let slot = lookup(&self.hasher, id, 0, self.primary.len());
+        self.backends[self.primary[slot]].id != backend
+    }
+}
+
+/// Shuffle sharding over a Maglev table: a tenant's shard is the backends found by `shard_size` independent
+/// lookups, each one stepping forward through the table if it lands on a backend the shard already has.
+///
+/// Like `RendevouzShuffle`, draining backends leave the table (and so every shard), while down backends stay in
+/// it so that a poison-pill tenant cannot walk its shard across the fleet.
+pub struct MaglevShuffle<R = SmallRng, H = SipHash13> {
+    backends: Vec<Backend>,
+    table: Vec<usize>,
+    /// The table as it would be with nothing draining, which holds every tenant's primary shard.
+    primary: Vec<usize>,
+    table_size: usize,
+    shard_size: usize,
+    hasher: H,
+    prng: R,
+}
+
+impl<R: Rng, H: HashFn + Default> MaglevShuffle<R, H> {
+    /// Like `Picker::new`, but `prng` chooses among the healthy members of the shard.
+    pub fn with_rng(shard_size: usize, prng: R) -> Self {
+        Self {
+            backends: Vec::new(),
+            table: Vec::new(),
+            primary: Vec::new(),
+            table_size: DEFAULT_TABLE_SIZE,
+            shard_size: shard_size.max(1),
+            hasher: H::default(),
+            prng,
+        }
+    }
+
+    /// Sets the lookup table size, which must be prime. A table smaller than the fleet leaves some backends out of
+    /// every shard, and shards may come up short.
+    pub fn with_table_size(mut self, table_size: usize) -> Self {
+        assert!(is_prime(table_size), "{table_size} is not prime");
+        self.table_size = table_size;
+        self.rebuild();
+        self
+    }
+
+    /// Replaces the hash function that places backends and tenants.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.backends = self
+            .backends
+            .iter()
+            .map(|b| b.hashed_with(&hasher))
+            .collect();
+        self.hasher = hasher;
+        self.rebuild();
+        self
+    }
+
+    fn eligible(b: &Backend) -> bool {
+        b.health != Health::Draining
+    }
+
+    /// Rebuilds both tables, after backends come, go or change weight.
+    fn rebuild(&mut self) {
+        self.primary = populate(&self.hasher, &self.backends, self.table_size, |_| true);
+        self.rebuild_eligible();
+    }
+
+    /// Rebuilds the table of eligible backends, after a health change.
+    fn rebuild_eligible(&mut self) {
+        self.table = if self.backends.iter().all(Self::eligible) {
+            self.primary.clone()
+        } else {
+            populate(
+                &self.hasher,
+                &self.backends,
+                self.table_size,
+                Self::eligible,
+            )
+        };
+    }
+
+    fn shard_members(&self, id: TenantId) -> Vec<Backend> {
+        let distinct = self.backends.iter().filter(|b| Self::eligible(b)).count();
+        probe_order(&self.hasher, &self.table, id, self.shard_size.min(distinct))
+            .into_iter()
+            .map(|i| self.backends[i])
+            .collect()
+    }
+
+    /// Whether `b` is in the tenant's shard only because draining backends left the table.
+    fn outside_primary_shard(&self, id: TenantId, b: BackendId) -> bool {
+        let members = self.shard_size.min(self.backends.len());
+        self.backends.iter().any(|b| b.health == Health::Draining)
+            && !probe_order(&self.hasher, &self.primary, id, members)
+                .into_iter()
+                .any(|i| self.backends[i].id == b)
+    }
+}
+
+impl<R: Rng + SeedableRng, H: HashFn + Default> Picker for MaglevShuffle<R, H> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_rng(shard_size, R::seed_from_u64(42))
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        if let Some(existing) = self.backends.iter_mut().find(|b| b.id == id) {
+            let before = *existing;
+            existing.health = health;
+            existing.weight = weight;
+            if before.weight == weight {
+                // Most health changes leave the table as it was, and rebuilding it isn't cheap.
+                if Self::eligible(&before) != Self::eligible(existing) {
+                    self.rebuild_eligible();
+                }
+                return;
+            }
+        } else {
+            self.backends
+                .push(Backend::new(id, health, weight).hashed_with(&self.hasher));
+            self.backends.sort();
+        }
+        self.rebuild();
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.backends.retain(|b| b.id != id);
+        self.rebuild();
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if self.backends.is_empty() {
+            return Err(PickError::NoBackends);
+        }
+        let healthy: Vec<Backend> = self
+            .shard_members(id)
+            .into_iter()
+            .filter(|b| b.health == Health::Up)
+            .collect();
+        let choice = healthy
+            .choose(&mut self.prng)
+            .ok_or(PickError::ShardUnavailable)?;
+        Ok(Pick::new(
+            choice.id,
+            self.outside_primary_shard(id, choice.id),
+        ))
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.shard_members(id)
+            .into_iter()
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.outside_primary_shard(id, backend)
+    }
+}
@@ -0,0 +1,139 @@
+//! Fleet-wide measurements of how well a picker spreads load and isolates tenants.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use anyhow::{bail, Context};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{BackendId, Health, Picker, TenantId};
+
+/// Standard deviation of the requests each of `num_backends` backends receives when `num_tenants` tenants send
+/// 100,000 requests between them, with 50 in flight at a time. Lower is better. Fails if there are no tenants to
+/// send requests, or if the picker can't route one.
+pub fn load_balancing(
+    p: &mut dyn Picker,
+    num_backends: usize,
+    num_tenants: u64,
+) -> anyhow::Result<f64> {
+    if num_tenants == 0 {
+        bail!("there are no tenants to send requests");
+    }
+    for i in 0..num_backends {
+        p.register(BackendId(i as u64), Health::Up);
+    }
+
+    let mut tally: Vec<usize> = vec![0; num_backends];
+    let mut prng = SmallRng::seed_from_u64(42);
+    // Requests complete in the order they were sent, with a fixed number in flight.
+    let mut in_flight = VecDeque::new();
+    for _ in 0..100_000 {
+        let tenant = TenantId(prng.gen_range(0..num_tenants));
+        let choice = p
+            .pick(tenant)
+            .with_context(|| format!("picking for {tenant:?} on {num_backends} backends"))?
+            .backend;
+        tally[choice.0 as usize] += 1;
+        in_flight.push_back(choice);
+        if in_flight.len() > 50 {
+            p.on_request_complete(in_flight.pop_front().unwrap());
+        }
+    }
+
+    let mean = tally.iter().copied().sum::<usize>() as f64 / num_backends as f64;
+    let variance = tally
+        .iter()
+        .copied()
+        .map(|x| {
+            let dx = x as f64 - mean;
+            dx * dx
+        })
+        .sum::<f64>()
+        / num_backends as f64;
+    Ok(variance.sqrt())
+}
+
+/// How well shards keep tenants apart, measured from exact shard membership.
+#[derive(Debug, Clone, Serialize)]
+pub struct Isolation {
+    /// Entry `i` is the fraction of pairs of distinct tenants whose shards share exactly `i` backends.
+    pub overlaps: Vec<f64>,
+    /// The same distribution if every shard were an independent, uniformly random `shard_size` subset of the fleet.
+    pub expected_overlaps: Vec<f64>,
+    /// The probability that a poison-pill tenant, by taking down its whole shard, fully takes out at least one other
+    /// tenant, i.e. that some other tenant's shard lies entirely inside its own.
+    pub takeout: f64,
+    /// The same probability for independent, uniformly random shards.
+    pub expected_takeout: f64,
+}
+
+/// Measures shard overlap across `num_tenants` tenants on a fleet of `num_backends`.
+pub fn tenant_isolation(
+    oracle: &mut dyn Picker,
+    shard_size: usize,
+    num_backends: u64,
+    num_tenants: u64,
+) -> Isolation {
+    for i in 0..num_backends {
+        oracle.register(BackendId(i), Health::Up);
+    }
+
+    let shards: BTreeMap<TenantId, BTreeSet<BackendId>> = (0..num_tenants)
+        .map(|tenant_id| {
+            let tenant_id = TenantId(tenant_id);
+            let shard = oracle
+                .shard(tenant_id)
+                .into_iter()
+                .map(|(b, _)| b)
+                .collect();
+            (tenant_id, shard)
+        })
+        .collect();
+    // Pickers whose shard is the whole fleet overlap by more than `shard_size`.
+    let largest = shards.values().map(BTreeSet::len).max().unwrap_or(0);
+    let mut overlaps = vec![0; shard_size.max(largest) + 1];
+    let mut takeouts = 0;
+    for (tenant_1, backends_1) in &shards {
+        let mut takes_out = false;
+        for (tenant_2, backends_2) in &shards {
+            if tenant_1 == tenant_2 {
+                continue;
+            }
+            let count = backends_1.intersection(backends_2).count();
+            overlaps[count] += 1;
+            takes_out |= !backends_2.is_empty() && count == backends_2.len();
+        }
+        takeouts += takes_out as usize;
+    }
+
+    let pairs = (num_tenants * num_tenants.saturating_sub(1)).max(1) as f64;
+    let n = num_backends as usize;
+    let k = shard_size.min(n);
+    let mut expected_overlaps = hypergeometric(n, k);
+    expected_overlaps.resize(overlaps.len().max(k + 1), 0.0);
+    Isolation {
+        overlaps: overlaps.into_iter().map(|c| c as f64 / pairs).collect(),
+        expected_overlaps,
+        takeout: takeouts as f64 / num_tenants.max(1) as f64,
+        // Another random shard lies inside ours only if it is the same k-subset.
+        expected_takeout: 1.0
+            - (1.0 - 1.0 / binomial(n, k)).powf(num_tenants.saturating_sub(1) as f64),
+    }
+}
+
+/// The distribution of how many backends two independent, uniformly random `k`-subsets of `n` backends share:
+/// entry `i` is `C(k, i) * C(n - k, k - i) / C(n, k)`.
+pub fn hypergeometric(n: usize, k: usize) -> Vec<f64> {
+    (0..=k)
+        .map(|i| {
+            if k - i > n - k {
+                return 0.0;
+            }
+            binomial(k, i) * binomial(n - k, k - i) / binomial(n, k)
+        })
+        .collect()
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k.min(n - k)).fold(1.0, |c, i| c * (n - i) as f64 / (i + 1) as f64)
+}
@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    Backend, BackendId, Health, Pick, PickError, PickResult, Picker, TenantId,
+};
+
+/// Number of times a tenant is hashed onto the ring. Appleton & O'Reilly report a peak-to-mean load ratio of about
+/// 1.05 with 21 probes and one point per backend.
+pub const DEFAULT_PROBES: u64 = 21;
+
+/// Multi-probe consistent hashing (Appleton & O'Reilly). Each backend has one point on the ring per unit of weight,
+/// a tenant hashes onto the ring several times, and it is served by whichever backend point follows one of its
+/// probes most closely.
+#[derive(Default)]
+struct Ring {
+    backends: BTreeMap<BackendId, Backend>,
+    points: Vec<(u64, BackendId)>,
+}
+
+impl Ring {
+    fn register(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.backends
+            .entry(id)
+            .and_modify(|b| {
+                b.health = health;
+                b.weight = weight;
+            })
+            .or_insert_with(|| Backend::new(id, health, weight));
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.backends.remove(&id);
+    }
+
+    /// Places every backend's points. Backend points are derived from the backend's own hash so that they never
+    /// coincide with a tenant's probes.
+    fn rebuild<H: HashFn>(&mut self, hasher: &H) {
+        self.points = self
+            .backends
+            .values()
+            .flat_map(|b| {
+                let hash = hasher.hash_u64(b.id.0);
+                (0..b.weight as u64).map(move |j| (hasher.hash_pair(hash, j), b.id))
+            })
+            .collect();
+        self.points.sort_unstable();
+    }
+
+    /// The `limit` backends accepted by `eligible` whose points lie closest after one of the tenant's probes,
+    /// closest first.
+    ///
+    /// A backend in the overall top `limit` is also among the first `limit` eligible backends clockwise of the
+    /// probe it is closest to, so walking each probe's successors that far is enough.
+    fn ranked<H: HashFn>(
+        &self,
+        hasher: &H,
+        probes: u64,
+        id: TenantId,
+        limit: usize,
+        eligible: impl Fn(&Backend) -> bool,
+    ) -> Vec<Backend> {
+        let mut closest: BTreeMap<BackendId, u64> = BTreeMap::new();
+        for probe in 0..probes {
+            let at = hasher.hash_pair(id.0, probe);
+            let start = self.points.partition_point(|&(point, _)| point < at);
+            let mut seen = BTreeSet::new();
+            for i in 0..self.points.len() {
+                if seen.len() >= limit {
+                    break;
+                }
+                let (point, b) = self.points[(start + i) % self.points.len()];
+                if seen.contains(&b) || !eligible(&self.backends[&b]) {
+                    continue;
+                }
+                seen.insert(b);
+                let distance = point.wrapping_sub(at);
+                closest
+                    .entry(b)
+                    .and_modify(|d| *d = (*d).min(distance))
+                    .or_insert(distance);
+            }
+        }
+        let mut ranked: Vec<(u64, BackendId)> = closest.into_iter().map(|(b, d)| (d, b)).collect();
+        ranked.sort_unstable();
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|(_, b)| self.backends[&b])
+            .collect()
+    }
+}
+
+/// Multi-probe consistent hashing. Unhealthy backends are skipped, so their tenants move to the next closest
+/// healthy point.
+pub struct MultiProbe<H = SipHash13> {
+    ring: Ring,
+    probes: u64,
+    hasher: H,
+}
+
+impl<H: HashFn> MultiProbe<H> {
+    /// Hashes each tenant `probes` times instead of `DEFAULT_PROBES`.
+    pub fn with_probes(mut self, probes: u64) -> Self {
+        assert!(probes > 0, "need at least one probe");
+        self.probes = probes;
+        self
+    }
+
+    /// Replaces the hash function and re-places every backend.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.hasher = hasher;
+        self.ring.rebuild(&self.hasher);
+        self
+    }
+}
+
+impl<H: HashFn + Default> Picker for MultiProbe<H> {
+    fn new(_shard_size: usize) -> Self {
+        Self {
+            ring: Ring::default(),
+            probes: DEFAULT_PROBES,
+            hasher: H::default(),
+        }
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        let placed = self.ring.backends.get(&id).map(|b| b.weight);
+        self.ring.register(id, health, weight);
+        if placed != Some(weight) {
+            self.ring.rebuild(&self.hasher);
+        }
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.ring.unregister(id);
+        self.ring.rebuild(&self.hasher);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        let ranked = |eligible: fn(&Backend) -> bool| {
+            self.ring
+                .ranked(&self.hasher, self.probes, id, 1, eligible)
+                .first()
+                .copied()
+        };
+        let first = ranked(|_| true).ok_or(PickError::NoBackends)?;
+        if first.health == Health::Up {
+            return Ok(Pick::primary(first.id));
+        }
+        let choice = ranked(|b| b.health == Health::Up).ok_or(PickError::ShardUnavailable)?;
+        Ok(Pick::new(choice.id, true))
+    }
+
+    /// The whole fleet, closest first.
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.ring
+            .ranked(&self.hasher, self.probes, id, usize::MAX, |_| true)
+            .into_iter()
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    /// Anything but the closest backend.
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.ring
+            .ranked(&self.hasher, self.probes, id, 1, |_| true)
+            .first()
+            .is_some_and(|closest| closest.id != backend)
+    }
+}
+
+/// Shuffle sharding with multi-probe consistent hashing: a tenant's shard is the `shard_size` non-draining backends
+/// closest to its probes, and requests go to a random healthy member.
+pub struct MultiProbeShuffle<R = SmallRng, H = SipHash13> {
+    ring: Ring,
+    shard_size: usize,
+    probes: u64,
+    hasher: H,
+    prng: R,
+}
+
+impl<R: Rng, H: HashFn + Default> MultiProbeShuffle<R, H> {
+    /// Like `Picker::new`, but `prng` chooses among the healthy members of the shard.
+    pub fn with_rng(shard_size: usize, prng: R) -> Self {
+        Self {
+            ring: Ring::default(),
+            shard_size: shard_size.max(1),
+            probes: DEFAULT_PROBES,
+            hasher: H::default(),
+            prng,
+        }
+    }
+
+    /// Hashes each tenant `probes` times instead of `DEFAULT_PROBES`.
+    pub fn with_probes(mut self, probes: u64) -> Self {
+        assert!(probes > 0, "need at least one probe");
+        self.probes = probes;
+        self
+    }
+
+    /// Replaces the hash function and re-places every backend.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.hasher = hasher;
+        self.ring.rebuild(&self.hasher);
+        self
+    }
+
+    fn shard_members(&self, id: TenantId) -> Vec<Backend> {
+        self.ring
+            .ranked(&self.hasher, self.probes, id, self.shard_size, |b| {
+                b.health != Health::Draining
+            })
+    }
+
+    /// Whether `b` is in the tenant's shard only because draining backends lie closer to its probes.
+    fn outside_primary_shard(&self, id: TenantId, b: BackendId) -> bool {
+        self.ring
+            .backends
+            .values()
+            .any(|b| b.health == Health::Draining)
+            && !self
+                .ring
+                .ranked(&self.hasher, self.probes, id, self.shard_size, |_| true)
+                .iter()
+                .any(|primary| primary.id == b)
+    }
+}
+
+impl<R: Rng + SeedableRng, H: HashFn + Default> Picker for MultiProbeShuffle<R, H> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_rng(shard_size, R::seed_from_u64(42))
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        let placed = self.ring.backends.get(&id).map(|b| b.weight);
+        self.ring.register(id, health, weight);
+        if placed != Some(weight) {
+            self.ring.rebuild(&self.hasher);
+        }
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.ring.unregister(id);
+        self.ring.rebuild(&self.hasher);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if self.ring.backends.is_empty() {
+            return Err(PickError::NoBackends);
+        }
+        let healthy: Vec<Backend> = self
+            .shard_members(id)
+            .into_iter()
+            .filter(|b| b.health == Health::Up)
+            .collect();
+        let choice = healthy
+            .choose(&mut self.prng)
+            .ok_or(PickError::ShardUnavailable)?;
+        Ok(Pick::new(
+            choice.id,
+            self.outside_primary_shard(id, choice.id),
+        ))
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.shard_members(id)
+            .into_iter()
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.outside_primary_shard(id, backend)
+    }
+}
@@ -0,0 +1,222 @@
+//! Outlier detection: ejecting backends that keep failing requests, without waiting to be told they are down.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, SystemTime},
+};
+
+use crate::{BackendId, Health, Outcome, PickResult, Picker, TenantId};
+
+/// Consecutive errors or timeouts that eject a backend.
+pub const DEFAULT_CONSECUTIVE_ERRORS: u32 = 5;
+/// A backend whose success rate over its last `DEFAULT_SUCCESS_RATE_WINDOW` outcomes falls below this is ejected.
+pub const DEFAULT_MIN_SUCCESS_RATE: f64 = 0.7;
+pub const DEFAULT_SUCCESS_RATE_WINDOW: usize = 50;
+/// How long a first ejection lasts. Each further ejection of the same backend lasts that much longer again, up to
+/// `MAX_EJECTION_MULTIPLIER` times as long.
+pub const DEFAULT_EJECTION_TIME: Duration = Duration::from_secs(30);
+const MAX_EJECTION_MULTIPLIER: u64 = 10;
+/// The largest fraction of the fleet that may be ejected at once.
+pub const DEFAULT_MAX_EJECTED: f64 = 0.1;
+
+#[derive(Debug, Default)]
+struct Stats {
+    consecutive_errors: u32,
+    /// The most recent outcomes, `true` for success.
+    recent: VecDeque<bool>,
+    /// How many times the backend has been ejected so far.
+    ejections: u64,
+    /// When an ejected backend is readmitted.
+    ejected_until: Option<SystemTime>,
+}
+
+/// Ejects misbehaving backends from any `Picker`.
+///
+/// Callers report how each request turned out through `report_outcome`. A backend that returns too many errors or
+/// timeouts in a row, or whose success rate over a window of recent requests drops too low, is registered with the
+/// inner picker as down until its ejection runs out, whatever health the caller last gave it. Ejections run out by
+/// the wall clock, however much or little traffic arrives in the meantime; `with_clock` replaces it, e.g. with a
+/// simulation's own.
+///
+/// Errors that follow a tenant around, such as a poison pill, look like every backend the tenant touches failing.
+/// To keep such a tenant from ejecting the whole fleet, no more than a fixed fraction of backends (but always at
+/// least one) is ejected at a time.
+pub struct OutlierDetection<P> {
+    inner: P,
+    /// Health and weight of every backend, as the caller registered it.
+    registered: BTreeMap<BackendId, (Health, u32)>,
+    stats: BTreeMap<BackendId, Stats>,
+    consecutive_errors: u32,
+    min_success_rate: f64,
+    success_rate_window: usize,
+    ejection_time: Duration,
+    max_ejected: f64,
+    clock: Box<dyn Fn() -> SystemTime>,
+}
+
+impl<P: Picker> OutlierDetection<P> {
+    /// Ejects outliers from the shards of `inner`.
+    pub fn with_picker(inner: P) -> Self {
+        Self {
+            inner,
+            registered: BTreeMap::new(),
+            stats: BTreeMap::new(),
+            consecutive_errors: DEFAULT_CONSECUTIVE_ERRORS,
+            min_success_rate: DEFAULT_MIN_SUCCESS_RATE,
+            success_rate_window: DEFAULT_SUCCESS_RATE_WINDOW,
+            ejection_time: DEFAULT_EJECTION_TIME,
+            max_ejected: DEFAULT_MAX_EJECTED,
+            clock: Box::new(SystemTime::now),
+        }
+    }
+
+    pub fn with_consecutive_errors(mut self, errors: u32) -> Self {
+        assert!(errors > 0, "ejecting on zero errors would eject everything");
+        self.consecutive_errors = errors;
+        self
+    }
+
+    /// Ejects backends whose success rate over their last `window` outcomes is below `min`.
+    pub fn with_success_rate(mut self, min: f64, window: usize) -> Self {
+        self.min_success_rate = min;
+        self.success_rate_window = window;
+        self
+    }
+
+    pub fn with_ejection_time(mut self, time: Duration) -> Self {
+        self.ejection_time = time;
+        self
+    }
+
+    /// Replaces the wall clock that ejections run out by.
+    pub fn with_clock(mut self, clock: impl Fn() -> SystemTime + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Caps ejections at this fraction of the fleet.
+    pub fn with_max_ejected(mut self, fraction: f64) -> Self {
+        self.max_ejected = fraction;
+        self
+    }
+
+    /// Backends that are currently ejected.
+    pub fn ejected(&self) -> Vec<BackendId> {
+        self.stats
+            .iter()
+            .filter(|(_, s)| s.ejected_until.is_some())
+            .map(|(&b, _)| b)
+            .collect()
+    }
+
+    fn is_outlier(&self, s: &Stats) -> bool {
+        if s.consecutive_errors >= self.consecutive_errors {
+            return true;
+        }
+        if s.recent.len() < self.success_rate_window || self.success_rate_window == 0 {
+            return false;
+        }
+        let successes = s.recent.iter().filter(|&&ok| ok).count();
+        (successes as f64) < self.min_success_rate * s.recent.len() as f64
+    }
+
+    fn eject(&mut self, id: BackendId) {
+        let cap = ((self.max_ejected * self.registered.len() as f64) as usize).max(1);
+        if self.ejected().len() >= cap {
+            return;
+        }
+        let Some(&(_, weight)) = self.registered.get(&id) else {
+            return;
+        };
+        let now = (self.clock)();
+        let s = self.stats.entry(id).or_default();
+        s.ejections += 1;
+        s.ejected_until =
+            Some(now + self.ejection_time * s.ejections.min(MAX_EJECTION_MULTIPLIER) as u32);
+        self.inner.register_weighted(id, Health::Down, weight);
+    }
+
+    /// Readmits every backend whose ejection has run out, with a clean record.
+    fn readmit_expired(&mut self) {
+        if self.stats.values().all(|s| s.ejected_until.is_none()) {
+            return;
+        }
+        let now = (self.clock)();
+        for (&id, s) in self.stats.iter_mut() {
+            if s.ejected_until.is_some_and(|until| until <= now) {
+                s.ejected_until = None;
+                s.consecutive_errors = 0;
+                s.recent.clear();
+                if let Some(&(health, weight)) = self.registered.get(&id) {
+                    self.inner.register_weighted(id, health, weight);
+                }
+            }
+        }
+    }
+}
+
+impl<P: Picker> Picker for OutlierDetection<P> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_picker(P::new(shard_size))
+    }
+
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.registered.insert(id, (health, weight));
+        // An ejected backend stays down until its ejection runs out.
+        let ejected = self
+            .stats
+            .get(&id)
+            .is_some_and(|s| s.ejected_until.is_some());
+        let health = if ejected { Health::Down } else { health };
+        self.inner.register_weighted(id, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.registered.remove(&id);
+        self.stats.remove(&id);
+        self.inner.unregister(id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        self.readmit_expired();
+        self.inner.pick(id)
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.inner.shard(id)
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.inner.falls_back(id, backend)
+    }
+
+    fn report_load(&mut self, id: BackendId, load: f64) {
+        self.inner.report_load(id, load);
+    }
+
+    fn on_request_complete(&mut self, id: BackendId) {
+        self.inner.on_request_complete(id);
+    }
+
+    fn report_outcome(&mut self, tenant: TenantId, id: BackendId, outcome: Outcome) {
+        self.inner.report_outcome(tenant, id, outcome);
+        if !self.registered.contains_key(&id) {
+            return;
+        }
+        let window = self.success_rate_window;
+        let s = self.stats.entry(id).or_default();
+        // Requests that were already in flight when the backend was ejected say nothing new.
+        if s.ejected_until.is_some() {
+            return;
+        }
+        let ok = outcome == Outcome::Success;
+        s.consecutive_errors = if ok { 0 } else { s.consecutive_errors + 1 };
+        s.recent.push_back(ok);
+        if s.recent.len() > window {
+            s.recent.pop_front();
+        }
+        if self.is_outlier(&self.stats[&id]) {
+            self.eject(id);
+        }
+    }
+}
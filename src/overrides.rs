@@ -0,0 +1,365 @@
+//! Operator overrides: pinning tenants to dedicated backends or named pools, from a table that can be reloaded while
+//! the process runs.
+//!
+//! The table is written in TOML:
+//!
+//! ```toml
+//! # Noisy tenants go here, and nobody else does.
+//! [pools.quarantine]
+//! backends = [28, 29]
+//! exclusive = true
+//!
+//! [[pins]]
+//! tenant = 7
+//! pool = "quarantine"
+//! # Seconds since the Unix epoch.
+//! expires = 1798761600
+//!
+//! # An enterprise tenant with backends of its own.
+//! [[pins]]
+//! tenant = 42
+//! backends = { start = 0, end = 3 }
+//! exclusive = true
+//! ```
+//!
+//! Backends accept either a list of ids or a `{ start = .., end = .. }` range, as in scenarios.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{
+    scenario::Selection, BackendId, Health, Outcome, PickError, PickResult, Picker, TenantId,
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverrideTable {
+    #[serde(default)]
+    pub pools: BTreeMap<String, Pool>,
+    #[serde(default)]
+    pub pins: Vec<Pin>,
+}
+
+/// A named set of backends that any number of tenants can be pinned to.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pool {
+    pub backends: Selection,
+    /// Keep every other tenant off these backends, whether or not anyone is pinned to the pool.
+    #[serde(default)]
+    pub exclusive: bool,
+}
+
+/// Routes one tenant to either a named pool or a set of backends of its own.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pin {
+    pub tenant: u64,
+    pub pool: Option<String>,
+    pub backends: Option<Selection>,
+    /// Keep every other tenant off `backends` for as long as the pin lasts. Pools set this for themselves.
+    #[serde(default)]
+    pub exclusive: bool,
+    /// When the pin lapses, in seconds since the Unix epoch. The tenant then goes back to the general pool.
+    pub expires: Option<u64>,
+}
+
+impl OverrideTable {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let s =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(&s).with_context(|| format!("parsing {}", path.display()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum GroupKey {
+    Pool(String),
+    /// The backends of one tenant's own pin.
+    Tenant(TenantId),
+}
+
+/// Backends that pinned tenants are routed among, with a picker of their own.
+struct Group<P> {
+    members: BTreeSet<BackendId>,
+    exclusive: bool,
+    picker: P,
+}
+
+struct ActivePin {
+    group: GroupKey,
+    expires: Option<SystemTime>,
+}
+
+/// Pins tenants to dedicated backends or named pools, in front of any `Picker`.
+///
+/// Tenants without a pin are routed by the inner picker over the general pool: every registered backend, less those
+/// that an exclusive pool or pin reserves. Each pool, and each pin with backends of its own, gets a separate picker
+/// of the same kind, so tenants quarantined together are still shuffle sharded among themselves. A pinned tenant
+/// never leaves its pool: when nothing in the pool is up its picks fail with `PickError::ShardUnavailable`.
+///
+/// Expired pins are dropped, and the backends they reserved rejoin the general pool, on the first pick after they
+/// expire. `reload` swaps in a new table from the file last loaded; a table that fails to load or doesn't make
+/// sense leaves the current one in place.
+pub struct Overrides<P> {
+    inner: P,
+    shard_size: usize,
+    /// Health and weight of every backend, as the caller registered it.
+    registered: BTreeMap<BackendId, (Health, u32)>,
+    groups: BTreeMap<GroupKey, Group<P>>,
+    pins: BTreeMap<TenantId, ActivePin>,
+    /// The file the table was loaded from, for `reload`.
+    path: Option<PathBuf>,
+    clock: Box<dyn Fn() -> SystemTime>,
+}
+
+impl<P: Picker> Overrides<P> {
+    /// Routes tenants without a pin with `inner`, and builds a picker with shards of `shard_size` for each pool.
+    pub fn with_picker(inner: P, shard_size: usize) -> Self {
+        Self {
+            inner,
+            shard_size,
+            registered: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            pins: BTreeMap::new(),
+            path: None,
+            clock: Box::new(SystemTime::now),
+        }
+    }
+
+    /// Replaces the wall clock that pins expire by.
+    pub fn with_clock(mut self, clock: impl Fn() -> SystemTime + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Loads the table from `path`, which later calls to `reload` read again.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        self.apply(&OverrideTable::load(path)?)?;
+        self.path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Reads the file last passed to `load` again.
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        let Some(path) = self.path.clone() else {
+            bail!("no override table has been loaded");
+        };
+        self.load(path)
+    }
+
+    /// Replaces every pool and pin with those in `table`.
+    ///
+    /// A pool or pin whose backends and exclusivity are unchanged keeps its picker, along with whatever load or
+    /// outcomes that picker has been told about.
+    pub fn apply(&mut self, table: &OverrideTable) -> anyhow::Result<()> {
+        let mut wanted = BTreeMap::new();
+        for (name, pool) in &table.pools {
+            wanted.insert(
+                GroupKey::Pool(name.clone()),
+                (members(&pool.backends), pool.exclusive),
+            );
+        }
+        let mut pins = BTreeMap::new();
+        for pin in &table.pins {
+            let tenant = TenantId(pin.tenant);
+            let group = match (&pin.pool, &pin.backends) {
+                (Some(pool), None) if table.pools.contains_key(pool) => {
+                    GroupKey::Pool(pool.clone())
+                }
+                (Some(pool), None) => bail!("{tenant:?} is pinned to unknown pool {pool:?}"),
+                (None, Some(backends)) => {
+                    wanted.insert(GroupKey::Tenant(tenant), (members(backends), pin.exclusive));
+                    GroupKey::Tenant(tenant)
+                }
+                _ => bail!("{tenant:?} must be pinned to either a pool or a set of backends"),
+            };
+            let expires = pin
+                .expires
+                .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+            if pins.insert(tenant, ActivePin { group, expires }).is_some() {
+                bail!("{tenant:?} is pinned more than once");
+            }
+        }
+        let mut groups = BTreeMap::new();
+        for (key, (members, exclusive)) in wanted {
+            let group = match self.groups.remove(&key) {
+                Some(group) if group.members == members && group.exclusive == exclusive => group,
+                _ => self.group(members, exclusive),
+            };
+            groups.insert(key, group);
+        }
+        self.groups = groups;
+        self.pins = pins;
+        self.rebalance();
+        Ok(())
+    }
+
+    fn group(&self, members: BTreeSet<BackendId>, exclusive: bool) -> Group<P> {
+        let mut picker = P::new(self.shard_size);
+        for b in &members {
+            if let Some(&(health, weight)) = self.registered.get(b) {
+                picker.register_weighted(*b, health, weight);
+            }
+        }
+        Group {
+            members,
+            exclusive,
+            picker,
+        }
+    }
+
+    /// The pin routing `id`, unless it has expired.
+    fn active_pin(&self, id: TenantId) -> Option<&ActivePin> {
+        self.pins
+            .get(&id)
+            .filter(|pin| pin.expires.is_none_or(|expires| expires > (self.clock)()))
+    }
+
+    /// Whether any exclusive pool or pin reserves `id`.
+    fn reserved(&self, id: BackendId) -> bool {
+        self.groups
+            .values()
+            .any(|g| g.exclusive && g.members.contains(&id))
+    }
+
+    /// Brings the general pool in line with the current reservations.
+    fn rebalance(&mut self) {
+        for (&id, &(health, weight)) in &self.registered {
+            if self.reserved(id) {
+                self.inner.unregister(id);
+            } else {
+                self.inner.register_weighted(id, health, weight);
+            }
+        }
+    }
+
+    /// Drops every pin that has expired, along with any backends reserved for it alone.
+    fn expire(&mut self) {
+        if self.pins.values().all(|pin| pin.expires.is_none()) {
+            return;
+        }
+        let now = (self.clock)();
+        let expired: Vec<TenantId> = self
+            .pins
+            .iter()
+            .filter(|(_, pin)| pin.expires.is_some_and(|expires| expires <= now))
+            .map(|(&t, _)| t)
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        for t in expired {
+            self.pins.remove(&t);
+            self.groups.remove(&GroupKey::Tenant(t));
+        }
+        self.rebalance();
+    }
+}
+
+fn members(backends: &Selection) -> BTreeSet<BackendId> {
+    backends.ids().into_iter().map(BackendId).collect()
+}
+
+impl<P: Picker> Picker for Overrides<P> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_picker(P::new(shard_size), shard_size)
+    }
+
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.registered.insert(id, (health, weight));
+        for group in self.groups.values_mut() {
+            if group.members.contains(&id) {
+                group.picker.register_weighted(id, health, weight);
+            }
+        }
+        if !self.reserved(id) {
+            self.inner.register_weighted(id, health, weight);
+        }
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.registered.remove(&id);
+        for group in self.groups.values_mut() {
+            group.picker.unregister(id);
+        }
+        self.inner.unregister(id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        self.expire();
+        let Some(pin) = self.pins.get(&id) else {
+            return self.inner.pick(id);
+        };
+        let group = self.groups.get_mut(&pin.group).unwrap();
+        match group.picker.pick(id) {
+            // The pool has no backends registered yet, but others do.
+            Err(PickError::NoBackends) if !self.registered.is_empty() => {
+                Err(PickError::ShardUnavailable)
+            }
+            picked => picked,
+        }
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        // An expired pin may not have been dropped yet, if nothing has been picked since.
+        match self.active_pin(id) {
+            Some(pin) => self.groups[&pin.group].picker.shard(id),
+            None => self.inner.shard(id),
+        }
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        match self.active_pin(id) {
+            Some(pin) => self.groups[&pin.group].picker.falls_back(id, backend),
+            None => self.inner.falls_back(id, backend),
+        }
+    }
+
+    fn report_load(&mut self, id: BackendId, load: f64) {
+        self.inner.report_load(id, load);
+        for group in self.groups.values_mut() {
+            if group.members.contains(&id) {
+                group.picker.report_load(id, load);
+            }
+        }
+    }
+
+    /// Reserved backends only ever serve pinned tenants, so the inner picker never routed the request.
+    fn on_request_complete(&mut self, id: BackendId) {
+        if !self.reserved(id) {
+            self.inner.on_request_complete(id);
+        }
+        for group in self.groups.values_mut() {
+            if group.members.contains(&id) {
+                group.picker.on_request_complete(id);
+            }
+        }
+    }
+
+    /// Only the picker that routes `tenant` hears about it, so a pinned tenant's errors don't count against it in
+    /// the general pool, or the other way around.
+    fn report_outcome(&mut self, tenant: TenantId, id: BackendId, outcome: Outcome) {
+        match self.active_pin(tenant).map(|pin| pin.group.clone()) {
+            Some(group) => self
+                .groups
+                .get_mut(&group)
+                .unwrap()
+                .picker
+                .report_outcome(tenant, id, outcome),
+            None => self.inner.report_outcome(tenant, id, outcome),
+        }
+    }
+}
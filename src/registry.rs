@@ -0,0 +1,89 @@
+//! Pickers by name, for choosing one at runtime.
+
+use anyhow::anyhow;
+
+use crate::{
+    block_picker::BlockPicker,
+    bounded_load::BoundedLoadRendezvous,
+    cell::CellRouter,
+    circuit_breaker::CircuitBreaker,
+    concurrent::ConcurrentRendevouzShuffle,
+    drain_aware_shuffle::DrainAwareShuffle,
+    jump_hash::{JumpHash, JumpShuffle},
+    least_loaded::LeastLoaded,
+    maglev::{Maglev, MaglevShuffle},
+    multi_probe::{MultiProbe, MultiProbeShuffle},
+    naive_shuffle::NaiveShuffle,
+    outlier::OutlierDetection,
+    rendevouz::Rendevouz,
+    rendevouz_shuffle::RendevouzShuffle,
+    ring::{Ring, RingShuffle},
+    Picker, RoundRobin,
+};
+
+/// Builds a picker with default settings for the given shard size.
+pub type Constructor = fn(usize) -> Box<dyn Picker>;
+
+fn build<P: Picker + 'static>(shard_size: usize) -> Box<dyn Picker> {
+    Box::new(P::new(shard_size))
+}
+
+/// Every picker the crate provides, by name, except `ZonedShuffle`. Scenarios register backends through `Picker`,
+/// which has no notion of zones, so it would see a single zone and play out exactly like `rendezvous-shuffle`.
+pub const PICKERS: &[(&str, Constructor)] = &[
+    ("round-robin", build::<RoundRobin>),
+    ("naive-shuffle", build::<NaiveShuffle>),
+    ("drain-aware-shuffle", build::<DrainAwareShuffle>),
+    ("block", build::<BlockPicker>),
+    ("rendezvous", build::<Rendevouz>),
+    ("rendezvous-shuffle", build::<RendevouzShuffle>),
+    (
+        "concurrent-rendezvous-shuffle",
+        build::<ConcurrentRendevouzShuffle>,
+    ),
+    ("bounded-load", build::<BoundedLoadRendezvous>),
+    (
+        "least-loaded-naive-shuffle",
+        build::<LeastLoaded<NaiveShuffle>>,
+    ),
+    (
+        "least-loaded-rendezvous-shuffle",
+        build::<LeastLoaded<RendevouzShuffle>>,
+    ),
+    (
+        "outlier-detection-naive-shuffle",
+        build::<OutlierDetection<NaiveShuffle>>,
+    ),
+    (
+        "circuit-breaker-naive-shuffle",
+        build::<CircuitBreaker<NaiveShuffle>>,
+    ),
+    (
+        "cell-rendezvous-shuffle",
+        build::<CellRouter<RendevouzShuffle>>,
+    ),
+    ("maglev", build::<Maglev>),
+    ("maglev-shuffle", build::<MaglevShuffle>),
+    ("jump", build::<JumpHash>),
+    ("jump-shuffle", build::<JumpShuffle>),
+    ("multi-probe", build::<MultiProbe>),
+    ("multi-probe-shuffle", build::<MultiProbeShuffle>),
+    ("ring", build::<Ring>),
+    ("ring-shuffle", build::<RingShuffle>),
+];
+
+/// Names of every registered picker.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    PICKERS.iter().map(|&(name, _)| name)
+}
+
+/// Builds the picker called `name`.
+pub fn picker(name: &str, shard_size: usize) -> anyhow::Result<Box<dyn Picker>> {
+    let (_, build) = PICKERS.iter().find(|&&(n, _)| n == name).ok_or_else(|| {
+        anyhow!(
+            "unknown picker {name:?}, expected one of: {}",
+            names().collect::<Vec<_>>().join(", ")
+        )
+    })?;
+    Ok(build(shard_size))
+}
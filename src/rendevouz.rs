@@ -1,28 +1,38 @@
-use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
-};
+use std::cmp::Reverse;
 
-use crate::{hash, Backend, BackendId, Health, Picker, TenantId};
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    weighted_score, Backend, BackendId, Health, Pick, PickError, PickResult, Picker, TenantId,
+};
 
-pub struct Rendevouz {
+pub struct Rendevouz<H = SipHash13> {
     backends: Vec<Backend>,
+    hasher: H,
+}
+impl<H: HashFn> Rendevouz<H> {
+    /// Replaces the hash function, e.g. to use a keyed or seeded variant.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    fn score(&self, id: TenantId, b: &Backend) -> u64 {
+        weighted_score(self.hasher.hash_pair(id.0, b.id.0), b.weight)
+    }
 }
-impl Picker for Rendevouz {
+impl<H: HashFn + Default> Picker for Rendevouz<H> {
     fn new(_shard_size: usize) -> Self {
         Self {
             backends: Vec::new(),
+            hasher: H::default(),
         }
     }
-    fn register(&mut self, id: BackendId, health: Health) {
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
         if let Some(existing) = self.backends.iter_mut().find(|b| b.id == id) {
             existing.health = health;
+            existing.weight = weight;
         } else {
-            self.backends.push(Backend {
-                id,
-                health,
-                hash: hash(id),
-            });
+            self.backends.push(Backend::new(id, health, weight));
         }
     }
 
@@ -30,16 +40,36 @@ impl Picker for Rendevouz {
         self.backends.retain(|b| b.id != id);
     }
 
-    fn pick(&mut self, id: TenantId) -> Option<BackendId> {
-        self.backends
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        let top = self
+            .backends
+            .iter()
+            .max_by_key(|b| self.score(id, b))
+            .ok_or(PickError::NoBackends)?;
+        if top.health == Health::Up {
+            return Ok(Pick::primary(top.id));
+        }
+        let choice = self
+            .backends
             .iter()
             .filter(|b| b.health == Health::Up)
-            .max_by_key(|b| {
-                let mut h = DefaultHasher::new();
-                id.hash(&mut h);
-                b.id.hash(&mut h);
-                h.finish()
-            })
-            .map(|b| b.id)
+            .max_by_key(|b| self.score(id, b))
+            .ok_or(PickError::ShardUnavailable)?;
+        Ok(Pick::new(choice.id, true))
+    }
+
+    /// The whole fleet, ranked. A tenant only ever uses the first healthy backend.
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        let mut ranked = self.backends.clone();
+        ranked.sort_by_key(|b| Reverse(self.score(id, b)));
+        ranked.into_iter().map(|b| (b.id, b.health)).collect()
+    }
+
+    /// Anything but the top-ranked backend.
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.backends
+            .iter()
+            .max_by_key(|b| self.score(id, b))
+            .is_some_and(|top| top.id != backend)
     }
 }
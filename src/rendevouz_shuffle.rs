@@ -1,33 +1,90 @@
+use std::cmp::Reverse;
+
 use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
 
-use crate::{combine, hash, Backend, BackendId, Health, Picker, TenantId};
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    rendezvous_score, Backend, BackendId, Health, Pick, PickError, PickResult, Picker, ShardSize,
+    TenantId,
+};
 
-pub struct RendevouzShuffle {
+pub struct RendevouzShuffle<R = SmallRng, H = SipHash13> {
     backends: Vec<Backend>,
-    shard_size: usize,
-    prng: SmallRng,
+    shard_size: ShardSize,
+    shard_seed: u64,
+    hasher: H,
+    prng: R,
 }
-impl Picker for RendevouzShuffle {
-    fn new(shard_size: usize) -> Self {
+impl<R: Rng, H: HashFn + Default> RendevouzShuffle<R, H> {
+    /// Like `Picker::new`, but `prng` chooses among the members of the shard.
+    pub fn with_rng(shard_size: usize, prng: R) -> Self {
         Self {
             backends: Vec::new(),
-            shard_size,
-            prng: SmallRng::seed_from_u64(42),
+            shard_size: ShardSize::Fixed(shard_size),
+            shard_seed: 0,
+            hasher: H::default(),
+            prng,
         }
     }
-    fn register(&mut self, id: BackendId, health: Health) {
-        if health == Health::Draining {
-            self.unregister(id);
-            return;
+
+    /// Replaces the hash function that places backends, e.g. to use a keyed or seeded variant.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.backends = self
+            .backends
+            .iter()
+            .map(|b| b.hashed_with(&hasher))
+            .collect();
+        self.hasher = hasher;
+        self
+    }
+
+    /// Mixes `shard_seed` into the tenant's rendezvous hash. Instances that share a shard seed agree on every
+    /// shard, whatever their `prng`.
+    pub fn with_shard_seed(mut self, shard_seed: u64) -> Self {
+        self.shard_seed = shard_seed;
+        self
+    }
+
+    /// Gives tenants shards of different sizes: each tenant's shard is its own number of highest-scoring backends.
+    pub fn with_shard_size(mut self, shard_size: ShardSize) -> Self {
+        self.shard_size = shard_size;
+        self
+    }
+}
+
+impl<R, H: HashFn> RendevouzShuffle<R, H> {
+    /// Whether `b` only made it into the tenant's shard because backends that outrank it are draining.
+    fn outside_primary_shard(&self, id: TenantId, b: BackendId) -> bool {
+        if self.backends.iter().all(|b| b.health != Health::Draining) {
+            return false;
         }
+        let th = self.hasher.hash_u64(id.0) ^ self.shard_seed;
+        let score = |b: &Backend| rendezvous_score(th, b);
+        self.backends
+            .iter()
+            .find(|other| other.id == b)
+            .is_none_or(|b| {
+                let outranked_by = self
+                    .backends
+                    .iter()
+                    .filter(|other| score(other) > score(b))
+                    .count();
+                outranked_by >= self.shard_size.of(id)
+            })
+    }
+}
+
+impl<R: Rng + SeedableRng, H: HashFn + Default> Picker for RendevouzShuffle<R, H> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_rng(shard_size, R::seed_from_u64(42))
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
         if let Some(existing) = self.backends.iter_mut().find(|b| b.id == id) {
             existing.health = health;
+            existing.weight = weight;
         } else {
-            self.backends.push(Backend {
-                id,
-                health,
-                hash: hash(id),
-            });
+            self.backends
+                .push(Backend::new(id, health, weight).hashed_with(&self.hasher));
         }
     }
 
@@ -35,39 +92,78 @@ impl Picker for RendevouzShuffle {
         self.backends.retain(|b| b.id != id);
     }
 
-    fn pick(&mut self, id: TenantId) -> Option<BackendId> {
-        assert!(self.backends.len() >= self.shard_size);
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if self.backends.is_empty() {
+            return Err(PickError::NoBackends);
+        }
+        // Draining backends leave the shard entirely, and a fleet smaller than a shard is shared by everyone.
+        let shard_size = self.shard_size.of(id).min(
+            self.backends
+                .iter()
+                .filter(|b| b.health != Health::Draining)
+                .count(),
+        );
+        if shard_size == 0 {
+            return Err(PickError::ShardUnavailable);
+        }
 
-        let th = hash(id);
-        self.backends
-            .select_nth_unstable_by_key(self.shard_size, |b| combine(th, b.hash));
+        // Weighted rendezvous: the shard is the `shard_size` highest-scoring backends that aren't draining.
+        let th = self.hasher.hash_u64(id.0) ^ self.shard_seed;
+        if shard_size < self.backends.len() {
+            self.backends.select_nth_unstable_by_key(shard_size, |b| {
+                (
+                    b.health == Health::Draining,
+                    Reverse(rendezvous_score(th, b)),
+                )
+            });
+        }
+        let shard = &self.backends[..shard_size];
 
         // Try to find a healthy endpoint. If we get lucky, we can save ourselves the trouble of counting them.
         for _ in 0..2 {
-            let choice = self.backends[..self.shard_size]
-                .choose(&mut self.prng)
-                .unwrap();
+            let choice = *shard.choose(&mut self.prng).unwrap();
             if choice.health == Health::Up {
-                return Some(choice.id);
+                return Ok(Pick::new(
+                    choice.id,
+                    self.outside_primary_shard(id, choice.id),
+                ));
             }
         }
         // If we don't get lucky, brute-force the problem. Filter out all the unhealthy backends, then choose one of the
         // remaining healthy ones.
-        let healthy = self.backends[..self.shard_size]
-            .iter()
-            .filter(|b| b.health == Health::Up)
-            .count();
+        let healthy = shard.iter().filter(|b| b.health == Health::Up).count();
         if healthy == 0 {
-            None
+            Err(PickError::ShardUnavailable)
         } else {
-            Some(
-                self.backends
-                    .iter()
-                    .filter(|b| b.health == Health::Up)
-                    .nth(self.prng.gen_range(0..healthy))
-                    .unwrap()
-                    .id,
-            )
+            let choice = *shard
+                .iter()
+                .filter(|b| b.health == Health::Up)
+                .nth(self.prng.gen_range(0..healthy))
+                .unwrap();
+            Ok(Pick::new(
+                choice.id,
+                self.outside_primary_shard(id, choice.id),
+            ))
         }
     }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        let th = self.hasher.hash_u64(id.0) ^ self.shard_seed;
+        let mut ranked: Vec<Backend> = self
+            .backends
+            .iter()
+            .filter(|b| b.health != Health::Draining)
+            .copied()
+            .collect();
+        ranked.sort_by_key(|b| Reverse(rendezvous_score(th, b)));
+        ranked
+            .into_iter()
+            .take(self.shard_size.of(id))
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.outside_primary_shard(id, backend)
+    }
 }
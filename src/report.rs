@@ -0,0 +1,80 @@
+//! Machine-readable results of running a scenario against a picker.
+
+use std::{collections::BTreeMap, io};
+
+use anyhow::anyhow;
+use serde::Serialize;
+
+/// What happened when one picker played one scenario.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub picker: String,
+    pub scenario: String,
+    pub passed: bool,
+    /// The first expectation the picker broke.
+    pub failure: Option<String>,
+    /// Requests sent, including ones that could not be routed.
+    pub requests: usize,
+    /// Requests the picker found no backend for.
+    pub unroutable: usize,
+    /// Requests the picker turned away because it had throttled their tenant.
+    pub throttled: usize,
+    /// Requests routed outside their tenant's primary shard.
+    pub fallbacks: usize,
+    /// Requests routed to a backend that was not up, or no longer registered.
+    pub unhealthy_picks: usize,
+    /// Distinct backends each tenant talked to over the whole scenario.
+    pub backends_per_tenant: BTreeMap<u64, usize>,
+    /// Requests each backend received. Backends still in the fleet at the end are listed even if they received none.
+    pub load: BTreeMap<u64, usize>,
+}
+
+impl Report {
+    /// The report as a pass/fail result.
+    pub fn result(&self) -> anyhow::Result<()> {
+        match &self.failure {
+            Some(reason) => Err(anyhow!("{reason}")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn to_json(reports: &[Report]) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(reports)?)
+    }
+
+    /// Writes reports as CSV in long form, one `picker,scenario,metric,key,value` row per measurement, which keeps
+    /// diffs line-oriented and pivots easily in a notebook. Per-tenant and per-backend metrics carry the tenant or
+    /// backend id as their key; the others leave it empty.
+    pub fn write_csv<W: io::Write>(reports: &[Report], w: W) -> anyhow::Result<()> {
+        let mut w = csv::Writer::from_writer(w);
+        w.write_record(["picker", "scenario", "metric", "key", "value"])?;
+        for r in reports {
+            let mut row = |metric: &str, key: String, value: String| {
+                w.write_record([&r.picker, &r.scenario, metric, &key, &value])
+            };
+            row("passed", String::new(), r.passed.to_string())?;
+            row(
+                "failure",
+                String::new(),
+                r.failure.clone().unwrap_or_default(),
+            )?;
+            row("requests", String::new(), r.requests.to_string())?;
+            row("unroutable", String::new(), r.unroutable.to_string())?;
+            row("throttled", String::new(), r.throttled.to_string())?;
+            row("fallbacks", String::new(), r.fallbacks.to_string())?;
+            row(
+                "unhealthy_picks",
+                String::new(),
+                r.unhealthy_picks.to_string(),
+            )?;
+            for (tenant, count) in &r.backends_per_tenant {
+                row("backends_per_tenant", tenant.to_string(), count.to_string())?;
+            }
+            for (backend, count) in &r.load {
+                row("load", backend.to_string(), count.to_string())?;
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
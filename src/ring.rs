@@ -0,0 +1,266 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    Backend, BackendId, Health, Pick, PickError, PickResult, Picker, TenantId,
+};
+
+/// Virtual nodes per unit of weight. Ketama uses 160 points per server, which keeps each backend's share of the
+/// ring within a few percent of its fair share.
+pub const DEFAULT_VNODES: u32 = 160;
+
+/// A Ketama-style hash ring: every backend owns `vnodes * weight` points, and a tenant belongs to the first point
+/// clockwise of its own hash.
+struct HashRing {
+    backends: BTreeMap<BackendId, Backend>,
+    points: Vec<(u64, BackendId)>,
+    vnodes: u32,
+}
+
+impl HashRing {
+    fn new() -> Self {
+        Self {
+            backends: BTreeMap::new(),
+            points: Vec::new(),
+            vnodes: DEFAULT_VNODES,
+        }
+    }
+
+    fn register<H: HashFn>(&mut self, hasher: &H, id: BackendId, health: Health, weight: u32) {
+        let placed = self.backends.get(&id).map(|b| b.weight);
+        self.backends
+            .entry(id)
+            .and_modify(|b| {
+                b.health = health;
+                b.weight = weight;
+            })
+            .or_insert_with(|| Backend::new(id, health, weight));
+        // Health changes are looked up while walking, so only placement changes touch the ring.
+        if placed != Some(weight) {
+            self.points.retain(|&(_, b)| b != id);
+            self.place(hasher, id, weight);
+            self.points.sort_unstable();
+        }
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        if self.backends.remove(&id).is_some() {
+            self.points.retain(|&(_, b)| b != id);
+        }
+    }
+
+    /// Appends `id`'s points. They are derived from the backend's own hash so they never coincide with tenant
+    /// hashes.
+    fn place<H: HashFn>(&mut self, hasher: &H, id: BackendId, weight: u32) {
+        let hash = hasher.hash_u64(id.0);
+        let count = self.vnodes as u64 * weight as u64;
+        self.points
+            .extend((0..count).map(|vnode| (hasher.hash_pair(hash, vnode), id)));
+    }
+
+    fn rebuild<H: HashFn>(&mut self, hasher: &H) {
+        self.points.clear();
+        let placements: Vec<(BackendId, u32)> =
+            self.backends.values().map(|b| (b.id, b.weight)).collect();
+        for (id, weight) in placements {
+            self.place(hasher, id, weight);
+        }
+        self.points.sort_unstable();
+    }
+
+    /// Distinct backends accepted by `eligible`, walking clockwise from the tenant's hash, stopping after `limit`.
+    fn walk<H: HashFn>(
+        &self,
+        hasher: &H,
+        id: TenantId,
+        limit: usize,
+        eligible: impl Fn(&Backend) -> bool,
+    ) -> Vec<Backend> {
+        let at = hasher.hash_u64(id.0);
+        let start = self.points.partition_point(|&(point, _)| point < at);
+        let mut found: Vec<Backend> = Vec::new();
+        let mut seen = BTreeSet::new();
+        for i in 0..self.points.len() {
+            if found.len() >= limit || seen.len() == self.backends.len() {
+                break;
+            }
+            let b = self.backends[&self.points[(start + i) % self.points.len()].1];
+            if seen.insert(b.id) && eligible(&b) {
+                found.push(b);
+            }
+        }
+        found
+    }
+}
+
+/// Classic consistent hashing. A tenant is served by the first healthy backend clockwise of its hash, so a backend
+/// going down hands its tenants to its ring neighbours.
+pub struct Ring<H = SipHash13> {
+    ring: HashRing,
+    hasher: H,
+}
+
+impl<H: HashFn> Ring<H> {
+    /// Places `vnodes` points per unit of weight instead of `DEFAULT_VNODES`.
+    pub fn with_vnodes(mut self, vnodes: u32) -> Self {
+        assert!(vnodes > 0, "need at least one virtual node");
+        self.ring.vnodes = vnodes;
+        self.ring.rebuild(&self.hasher);
+        self
+    }
+
+    /// Replaces the hash function and re-places every backend.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.hasher = hasher;
+        self.ring.rebuild(&self.hasher);
+        self
+    }
+}
+
+impl<H: HashFn + Default> Picker for Ring<H> {
+    fn new(_shard_size: usize) -> Self {
+        Self {
+            ring: HashRing::new(),
+            hasher: H::default(),
+        }
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.ring.register(&self.hasher, id, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.ring.unregister(id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        let walk = |eligible: fn(&Backend) -> bool| {
+            self.ring
+                .walk(&self.hasher, id, 1, eligible)
+                .first()
+                .copied()
+        };
+        let first = walk(|_| true).ok_or(PickError::NoBackends)?;
+        if first.health == Health::Up {
+            return Ok(Pick::primary(first.id));
+        }
+        let choice = walk(|b| b.health == Health::Up).ok_or(PickError::ShardUnavailable)?;
+        Ok(Pick::new(choice.id, true))
+    }
+
+    /// The whole fleet, in clockwise order from the tenant.
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.ring
+            .walk(&self.hasher, id, usize::MAX, |_| true)
+            .into_iter()
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    /// Anything but the first backend clockwise of the tenant.
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.ring
+            .walk(&self.hasher, id, 1, |_| true)
+            .first()
+            .is_some_and(|first| first.id != backend)
+    }
+}
+
+/// Shuffle sharding on a hash ring: a tenant's shard is the first `shard_size` distinct non-draining backends
+/// clockwise of its hash, and requests go to a random healthy member. Down backends keep their place in the shard
+/// so that a poison-pill tenant cannot walk around the ring.
+pub struct RingShuffle<R = SmallRng, H = SipHash13> {
+    ring: HashRing,
+    shard_size: usize,
+    hasher: H,
+    prng: R,
+}
+
+impl<R: Rng, H: HashFn + Default> RingShuffle<R, H> {
+    /// Like `Picker::new`, but `prng` chooses among the healthy members of the shard.
+    pub fn with_rng(shard_size: usize, prng: R) -> Self {
+        Self {
+            ring: HashRing::new(),
+            shard_size: shard_size.max(1),
+            hasher: H::default(),
+            prng,
+        }
+    }
+
+    /// Places `vnodes` points per unit of weight instead of `DEFAULT_VNODES`.
+    pub fn with_vnodes(mut self, vnodes: u32) -> Self {
+        assert!(vnodes > 0, "need at least one virtual node");
+        self.ring.vnodes = vnodes;
+        self.ring.rebuild(&self.hasher);
+        self
+    }
+
+    /// Replaces the hash function and re-places every backend.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.hasher = hasher;
+        self.ring.rebuild(&self.hasher);
+        self
+    }
+
+    fn shard_members(&self, id: TenantId) -> Vec<Backend> {
+        self.ring.walk(&self.hasher, id, self.shard_size, |b| {
+            b.health != Health::Draining
+        })
+    }
+
+    /// Whether `b` is in the tenant's shard only because the walk passed over draining backends to reach it.
+    fn outside_primary_shard(&self, id: TenantId, b: BackendId) -> bool {
+        self.ring
+            .backends
+            .values()
+            .any(|b| b.health == Health::Draining)
+            && !self
+                .ring
+                .walk(&self.hasher, id, self.shard_size, |_| true)
+                .iter()
+                .any(|primary| primary.id == b)
+    }
+}
+
+impl<R: Rng + SeedableRng, H: HashFn + Default> Picker for RingShuffle<R, H> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_rng(shard_size, R::seed_from_u64(42))
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.ring.register(&self.hasher, id, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.ring.unregister(id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if self.ring.backends.is_empty() {
+            return Err(PickError::NoBackends);
+        }
+        let healthy: Vec<Backend> = self
+            .shard_members(id)
+            .into_iter()
+            .filter(|b| b.health == Health::Up)
+            .collect();
+        let choice = healthy
+            .choose(&mut self.prng)
+            .ok_or(PickError::ShardUnavailable)?;
+        Ok(Pick::new(
+            choice.id,
+            self.outside_primary_shard(id, choice.id),
+        ))
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.shard_members(id)
+            .into_iter()
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.outside_primary_shard(id, backend)
+    }
+}
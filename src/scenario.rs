@@ -0,0 +1,562 @@
+//! Declarative scenarios for exercising pickers.
+//!
+//! A scenario describes a fleet, the tenants sending traffic to it, a timeline of operational events and the
+//! expectations that must hold throughout. Scenarios are written in TOML:
+//!
+//! ```toml
+//! name = "health aware"
+//! backends = 30
+//! shard_size = 5
+//! tenants = 100
+//!
+//! [[timeline]]
+//! event = "down"
+//! backends = [0]
+//!
+//! [[timeline]]
+//! event = "traffic"
+//! requests = 100
+//! ```
+//!
+//! The fleet starts as backends `0..backends`, all up, and tenants are `0..tenants`. Events that take a set of
+//! backends or tenants accept either a list of ids or a `{ start = .., end = .. }` range.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
+use serde::Deserialize;
+
+use crate::{
+    report::Report, BackendId, Health, Outcome, PickError, Picker, TenantId, DEFAULT_WEIGHT,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    /// Size of the initial fleet.
+    pub backends: u64,
+    pub shard_size: usize,
+    /// Number of tenants that send traffic when a traffic step does not name its own.
+    #[serde(default = "default_tenants")]
+    pub tenants: u64,
+    /// Requests outstanding at once. Requests finish in the order they were sent, and once more than this many are
+    /// outstanding the oldest one completes.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Seed for anything the scenario randomizes, such as the order of a rolling restart.
+    #[serde(default = "default_seed")]
+    pub seed: u64,
+    pub timeline: Vec<Event>,
+    #[serde(default)]
+    pub expect: Expectations,
+}
+
+fn default_tenants() -> u64 {
+    100
+}
+
+fn default_concurrency() -> usize {
+    10
+}
+
+fn default_seed() -> u64 {
+    42
+}
+
+/// A set of backend or tenant ids.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Selection {
+    Ids(Vec<u64>),
+    Range { start: u64, end: u64 },
+}
+
+impl Selection {
+    pub fn ids(&self) -> Vec<u64> {
+        match self {
+            Selection::Ids(ids) => ids.clone(),
+            Selection::Range { start, end } => (*start..*end).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Event {
+    Drain {
+        backends: Selection,
+    },
+    Down {
+        backends: Selection,
+    },
+    Up {
+        backends: Selection,
+    },
+    /// Adds backends to the fleet, or changes the weight of ones already in it.
+    Register {
+        backends: Selection,
+        #[serde(default = "default_weight")]
+        weight: u32,
+    },
+    Unregister {
+        backends: Selection,
+    },
+    /// From now on, every backend that serves one of these tenants goes down.
+    Poison {
+        tenants: Selection,
+    },
+    /// Backends go down without the picker being told. Only the errors on requests routed to them give it away.
+    Crash {
+        backends: Selection,
+    },
+    /// From now on, every request from these tenants fails, without harming the backend that served it.
+    Fail {
+        tenants: Selection,
+    },
+    /// Every tenant (or the named ones) sends `requests` requests.
+    Traffic {
+        requests: usize,
+        tenants: Option<Selection>,
+    },
+    /// Drains `batch` backends one by one and then brings them back one by one, sending traffic after every
+    /// change. Each batch starts `stride` backends after the previous one, so a stride smaller than the batch
+    /// restarts some backends more than once. Both must be at least 1, and the batch no larger than the fleet.
+    RollingRestart {
+        batch: usize,
+        stride: Option<usize>,
+        /// Restart the fleet in a random order rather than by id.
+        #[serde(default)]
+        shuffle: bool,
+        requests: usize,
+        /// Sent once the whole batch is drained, when the fewest backends are up.
+        #[serde(default)]
+        peak_requests: usize,
+        tenants: Option<Selection>,
+    },
+    /// Replaces every backend with a new one, one at a time: the replacement is registered, then the original is
+    /// unregistered, with traffic after each step.
+    Recycle {
+        requests: usize,
+        tenants: Option<Selection>,
+    },
+}
+
+fn default_weight() -> u32 {
+    DEFAULT_WEIGHT
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Expectations {
+    /// Every request finds a backend.
+    pub always_routable: bool,
+    /// Requests only go to backends that are up.
+    pub only_healthy: bool,
+    /// When requests may go to backends that are not up, at most this many do.
+    pub max_unhealthy_picks: Option<usize>,
+    /// No more than this many tenants are ever throttled.
+    pub max_throttled_tenants: Option<usize>,
+    /// At least this many backends are still up at the end.
+    pub min_up_backends: Option<usize>,
+    /// No tenant talks to more than this many distinct backends over the whole scenario.
+    pub max_backends_per_tenant: Option<usize>,
+    /// Every backend in the final fleet receives at least this fraction of an even split of all requests.
+    pub min_fair_share: Option<f64>,
+}
+
+impl Default for Expectations {
+    fn default() -> Self {
+        Self {
+            always_routable: true,
+            only_healthy: true,
+            max_unhealthy_picks: None,
+            max_throttled_tenants: None,
+            min_up_backends: None,
+            max_backends_per_tenant: None,
+            min_fair_share: None,
+        }
+    }
+}
+
+impl Scenario {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let scenario: Self = toml::from_str(s)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Rejects events that cannot be played, before any of them are. The fleet is followed through the timeline
+    /// as `run` would change it, so that a rolling restart can be checked against the fleet it will find.
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut fleet: BTreeSet<u64> = (0..self.backends).collect();
+        let mut next_id = self.backends;
+        for (i, event) in self.timeline.iter().enumerate() {
+            match event {
+                Event::Register { weight: 0, .. } => {
+                    bail!("timeline event {i}: backends need a weight of at least 1")
+                }
+                Event::Register { backends, .. } => {
+                    for b in backends.ids() {
+                        fleet.insert(b);
+                        next_id = next_id.max(b + 1);
+                    }
+                }
+                Event::Unregister { backends } => {
+                    for b in backends.ids() {
+                        fleet.remove(&b);
+                    }
+                }
+                Event::Recycle { .. } => {
+                    fleet = (next_id..next_id + fleet.len() as u64).collect();
+                    next_id += fleet.len() as u64;
+                }
+                Event::RollingRestart { batch, stride, .. } => {
+                    if *batch == 0 || *stride == Some(0) {
+                        bail!("timeline event {i}: a rolling restart needs a batch and stride of at least 1");
+                    }
+                    if *batch > fleet.len() {
+                        bail!(
+                            "timeline event {i}: a rolling restart in batches of {batch} needs at least that many \
+                             backends, but the fleet has {}",
+                            fleet.len()
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let s =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(&s).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Plays the timeline against a fresh `P`, failing on the first broken expectation.
+    pub fn run<P: Picker>(&self) -> anyhow::Result<()> {
+        self.run_with(&mut P::new(self.shard_size))
+    }
+
+    /// Like `run`, for a picker that has already been configured, such as one built by the `registry`.
+    pub fn run_with<P: Picker + ?Sized>(&self, picker: &mut P) -> anyhow::Result<()> {
+        self.report_with("", picker).result()
+    }
+
+    /// Plays the whole timeline against `picker`, which is reported as `name`, and records how it behaved.
+    pub fn report_with<P: Picker + ?Sized>(&self, name: &str, picker: &mut P) -> Report {
+        let mut run = Run {
+            scenario: self,
+            picker,
+            fleet: BTreeMap::new(),
+            next_id: self.backends,
+            in_flight: VecDeque::new(),
+            poisoned: BTreeSet::new(),
+            failing: BTreeSet::new(),
+            touched: BTreeMap::new(),
+            tally: BTreeMap::new(),
+            sent: 0,
+            requests: 0,
+            unroutable: 0,
+            throttled: BTreeMap::new(),
+            fallbacks: 0,
+            unhealthy_picks: 0,
+            failure: None,
+        };
+        for b in (0..self.backends).map(BackendId) {
+            run.set(b, Health::Up);
+        }
+        for event in &self.timeline {
+            run.apply(event);
+        }
+        if let Err(e) = run.check() {
+            run.fail(e.to_string());
+        }
+
+        let mut load: BTreeMap<u64, usize> = run.fleet.keys().map(|b| (b.0, 0)).collect();
+        load.extend(run.tally.iter().map(|(b, &n)| (b.0, n)));
+        Report {
+            picker: name.to_string(),
+            scenario: self.name.clone(),
+            passed: run.failure.is_none(),
+            failure: run.failure,
+            requests: run.requests,
+            unroutable: run.unroutable,
+            throttled: run.throttled.values().sum(),
+            fallbacks: run.fallbacks,
+            unhealthy_picks: run.unhealthy_picks,
+            backends_per_tenant: run
+                .touched
+                .iter()
+                .map(|(t, backends)| (t.0, backends.len()))
+                .collect(),
+            load,
+        }
+    }
+}
+
+struct Run<'a, P: ?Sized> {
+    scenario: &'a Scenario,
+    picker: &'a mut P,
+    /// Health and weight of every registered backend.
+    fleet: BTreeMap<BackendId, (Health, u32)>,
+    /// Id for the next backend a recycle brings up.
+    next_id: u64,
+    in_flight: VecDeque<BackendId>,
+    poisoned: BTreeSet<TenantId>,
+    failing: BTreeSet<TenantId>,
+    touched: BTreeMap<TenantId, BTreeSet<BackendId>>,
+    tally: BTreeMap<BackendId, usize>,
+    sent: usize,
+    requests: usize,
+    unroutable: usize,
+    /// Requests turned away, by tenant.
+    throttled: BTreeMap<TenantId, usize>,
+    fallbacks: usize,
+    unhealthy_picks: usize,
+    failure: Option<String>,
+}
+
+impl<P: Picker + ?Sized> Run<'_, P> {
+    /// Changes a backend's health, keeping its weight.
+    fn set(&mut self, b: BackendId, health: Health) {
+        let weight = self.fleet.get(&b).map_or(DEFAULT_WEIGHT, |&(_, w)| w);
+        self.fleet.insert(b, (health, weight));
+        self.picker.register_weighted(b, health, weight);
+    }
+
+    fn tenants(&self, tenants: &Option<Selection>) -> Vec<TenantId> {
+        match tenants {
+            Some(s) => s.ids().into_iter().map(TenantId).collect(),
+            None => (0..self.scenario.tenants).map(TenantId).collect(),
+        }
+    }
+
+    fn apply(&mut self, event: &Event) {
+        let backends =
+            |s: &Selection| -> Vec<BackendId> { s.ids().into_iter().map(BackendId).collect() };
+        match event {
+            Event::Drain { backends: s } => backends(s)
+                .into_iter()
+                .for_each(|b| self.set(b, Health::Draining)),
+            Event::Down { backends: s } => backends(s)
+                .into_iter()
+                .for_each(|b| self.set(b, Health::Down)),
+            Event::Up { backends: s } => backends(s)
+                .into_iter()
+                .for_each(|b| self.set(b, Health::Up)),
+            Event::Register {
+                backends: s,
+                weight,
+            } => {
+                for b in backends(s) {
+                    let health = self.fleet.get(&b).map_or(Health::Up, |&(h, _)| h);
+                    self.fleet.insert(b, (health, *weight));
+                    self.picker.register_weighted(b, health, *weight);
+                    self.next_id = self.next_id.max(b.0 + 1);
+                }
+            }
+            Event::Unregister { backends: s } => {
+                for b in backends(s) {
+                    self.fleet.remove(&b);
+                    self.picker.unregister(b);
+                }
+            }
+            Event::Poison { tenants } => {
+                self.poisoned
+                    .extend(tenants.ids().into_iter().map(TenantId));
+            }
+            Event::Crash { backends: s } => {
+                for b in backends(s) {
+                    if let Some((health, _)) = self.fleet.get_mut(&b) {
+                        *health = Health::Down;
+                    }
+                }
+            }
+            Event::Fail { tenants } => {
+                self.failing.extend(tenants.ids().into_iter().map(TenantId));
+            }
+            Event::Traffic { requests, tenants } => {
+                self.traffic(&self.tenants(tenants), *requests);
+            }
+            Event::RollingRestart {
+                batch,
+                stride,
+                shuffle,
+                requests,
+                peak_requests,
+                tenants,
+            } => {
+                let tenants = self.tenants(tenants);
+                let mut order: Vec<BackendId> = self.fleet.keys().copied().collect();
+                if *shuffle {
+                    order.shuffle(&mut SmallRng::seed_from_u64(self.scenario.seed));
+                }
+                for stage in order.windows(*batch).step_by(stride.unwrap_or(*batch)) {
+                    for &b in stage {
+                        self.set(b, Health::Draining);
+                        self.traffic(&tenants, *requests);
+                    }
+                    self.traffic(&tenants, *peak_requests);
+                    for &b in stage {
+                        self.set(b, Health::Up);
+                        self.traffic(&tenants, *requests);
+                    }
+                }
+            }
+            Event::Recycle { requests, tenants } => {
+                let tenants = self.tenants(tenants);
+                let originals: Vec<BackendId> = self.fleet.keys().copied().collect();
+                for old in originals {
+                    let new = BackendId(self.next_id);
+                    self.next_id += 1;
+                    self.set(new, Health::Up);
+                    self.traffic(&tenants, *requests);
+
+                    self.fleet.remove(&old);
+                    self.picker.unregister(old);
+                    self.traffic(&tenants, *requests);
+                }
+            }
+        }
+    }
+
+    fn traffic(&mut self, tenants: &[TenantId], requests: usize) {
+        for &tenant_id in tenants {
+            for _ in 0..requests {
+                self.request(tenant_id);
+            }
+        }
+    }
+
+    /// Records a broken expectation. The scenario plays on so that the report covers all of it, but only the
+    /// first failure is kept as the reason.
+    fn fail(&mut self, reason: String) {
+        self.failure.get_or_insert(reason);
+    }
+
+    fn request(&mut self, tenant_id: TenantId) {
+        self.requests += 1;
+        let b = match self.picker.pick(tenant_id) {
+            Ok(pick) => {
+                self.fallbacks += pick.fallback as usize;
+                pick.backend
+            }
+            Err(PickError::TenantThrottled) => {
+                *self.throttled.entry(tenant_id).or_default() += 1;
+                return;
+            }
+            Err(e) => {
+                self.unroutable += 1;
+                if self.scenario.expect.always_routable {
+                    self.fail(format!("could not route request for {tenant_id:?}: {e}"));
+                }
+                return;
+            }
+        };
+        let healthy = matches!(self.fleet.get(&b), Some(&(Health::Up, _)));
+        // A poison pill crashes the backend while serving the request.
+        let outcome =
+            if healthy && !self.failing.contains(&tenant_id) && !self.poisoned.contains(&tenant_id)
+            {
+                Outcome::Success
+            } else {
+                Outcome::Error
+            };
+        self.picker.report_outcome(tenant_id, b, outcome);
+        match self.fleet.get(&b) {
+            Some(&(Health::Up, _)) => {}
+            Some(_) => {
+                self.unhealthy_picks += 1;
+                if self.scenario.expect.only_healthy {
+                    self.fail(format!(
+                        "tenant {tenant_id:?} got routed to an unhealthy backend"
+                    ));
+                }
+            }
+            None => {
+                self.unhealthy_picks += 1;
+                self.fail(format!(
+                    "tenant {tenant_id:?} got routed to unregistered {b:?}"
+                ));
+            }
+        }
+
+        self.sent += 1;
+        *self.tally.entry(b).or_default() += 1;
+        self.touched.entry(tenant_id).or_default().insert(b);
+        self.in_flight.push_back(b);
+        while self.in_flight.len() > self.scenario.concurrency {
+            let done = self.in_flight.pop_front().unwrap();
+            self.picker.on_request_complete(done);
+        }
+
+        if self.poisoned.contains(&tenant_id) && self.fleet.contains_key(&b) {
+            self.set(b, Health::Down);
+        }
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        let expect = &self.scenario.expect;
+        if let Some(max) = expect.max_unhealthy_picks {
+            if self.unhealthy_picks > max {
+                bail!(
+                    "{} requests went to backends that were not up, expected at most {max}",
+                    self.unhealthy_picks
+                );
+            }
+        }
+        if let Some(max) = expect.max_throttled_tenants {
+            if self.throttled.len() > max {
+                bail!(
+                    "{} tenants were throttled, expected at most {max}: {:?}",
+                    self.throttled.len(),
+                    self.throttled.keys().collect::<Vec<_>>()
+                );
+            }
+        }
+        if let Some(min) = expect.min_up_backends {
+            let up = self
+                .fleet
+                .values()
+                .filter(|&&(h, _)| h == Health::Up)
+                .count();
+            if up < min {
+                bail!("only {up} backends are still up, expected at least {min}");
+            }
+        }
+        if let Some(max) = expect.max_backends_per_tenant {
+            for (tenant_id, touched) in &self.touched {
+                if touched.len() > max {
+                    bail!(
+                        "tenant {tenant_id:?} sprawled out to {} backends, more than {max}",
+                        touched.len()
+                    );
+                }
+            }
+        }
+        if let Some(share) = expect.min_fair_share {
+            if self.fleet.is_empty() {
+                bail!("no backends are left to share the requests");
+            }
+            let fair = self.sent as f64 / self.fleet.len() as f64;
+            for b in self.fleet.keys() {
+                let recv = self.tally.get(b).copied().unwrap_or_default();
+                if (recv as f64) < fair * share {
+                    bail!(
+                        "{b:?} received {recv} which is less than {:.0}% of {fair:.0}",
+                        share * 100.0
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+//! Discrete-event traffic simulation.
+//!
+//! Scenarios treat requests as instantaneous. The simulator instead plays traffic out over time: every tenant sends
+//! requests according to its own arrival process, every backend serves a limited number of requests at once and
+//! queues the rest, and clients give up on requests that take longer than a timeout. What comes out is the latency
+//! each tenant saw, so a slow backend or a noisy tenant shows up as tail latency for the tenants that share with it.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+    f64::consts::TAU,
+    time::Duration,
+};
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{BackendId, Health, Outcome, PickError, Picker, TenantId};
+
+/// How a tenant's requests arrive. Rates are in requests per second.
+#[derive(Debug, Clone)]
+pub enum Arrival {
+    /// Requests arrive independently at a steady rate.
+    Poisson { rate: f64 },
+    /// Poisson arrivals at `burst_rate` for `on`, then at `rate` for `off`, repeating.
+    Bursty {
+        rate: f64,
+        burst_rate: f64,
+        on: Duration,
+        off: Duration,
+    },
+    /// Poisson arrivals whose rate swings sinusoidally between `rate * (1 - amplitude)` and
+    /// `rate * (1 + amplitude)` once every `period`.
+    Diurnal {
+        rate: f64,
+        amplitude: f64,
+        period: Duration,
+    },
+}
+
+impl Arrival {
+    fn rate_at(&self, t: Duration) -> f64 {
+        match *self {
+            Arrival::Poisson { rate } => rate,
+            Arrival::Bursty {
+                rate,
+                burst_rate,
+                on,
+                off,
+            } => {
+                let phase = t.as_secs_f64() % (on + off).as_secs_f64();
+                if phase < on.as_secs_f64() {
+                    burst_rate
+                } else {
+                    rate
+                }
+            }
+            Arrival::Diurnal {
+                rate,
+                amplitude,
+                period,
+            } => rate * (1.0 + amplitude * (TAU * t.as_secs_f64() / period.as_secs_f64()).sin()),
+        }
+    }
+
+    fn peak_rate(&self) -> f64 {
+        match *self {
+            Arrival::Poisson { rate } => rate,
+            Arrival::Bursty {
+                rate, burst_rate, ..
+            } => rate.max(burst_rate),
+            Arrival::Diurnal {
+                rate, amplitude, ..
+            } => rate * (1.0 + amplitude.abs()),
+        }
+    }
+
+    /// The first arrival after `now`, if it comes before `until`. Arrivals are drawn at the peak rate and thinned
+    /// down to the rate at the time they land.
+    fn next(&self, now: Duration, until: Duration, prng: &mut impl Rng) -> Option<Duration> {
+        let peak = self.peak_rate();
+        if peak <= 0.0 {
+            return None;
+        }
+        let mut t = now;
+        loop {
+            t += Duration::from_secs_f64(exponential(1.0 / peak, prng));
+            if t >= until {
+                return None;
+            }
+            if prng.gen::<f64>() * peak < self.rate_at(t) {
+                return Some(t);
+            }
+        }
+    }
+}
+
+/// How long a backend takes to serve one request once it starts on it.
+#[derive(Debug, Clone, Copy)]
+pub enum ServiceTime {
+    Fixed(Duration),
+    /// Exponentially distributed around the given mean.
+    Exponential(Duration),
+}
+
+impl ServiceTime {
+    fn sample(&self, prng: &mut impl Rng) -> Duration {
+        match *self {
+            ServiceTime::Fixed(d) => d,
+            ServiceTime::Exponential(mean) => {
+                Duration::from_secs_f64(exponential(mean.as_secs_f64(), prng))
+            }
+        }
+    }
+}
+
+fn exponential(mean: f64, prng: &mut impl Rng) -> f64 {
+    -mean * (1.0 - prng.gen::<f64>()).ln()
+}
+
+/// A simulated backend.
+#[derive(Debug, Clone, Copy)]
+pub struct Server {
+    pub service: ServiceTime,
+    /// Requests served at once. Any more wait in a FIFO queue.
+    pub concurrency: usize,
+}
+
+impl Server {
+    pub fn new(service: ServiceTime, concurrency: usize) -> Self {
+        assert!(
+            concurrency > 0,
+            "a server must serve at least one request at once"
+        );
+        Self {
+            service,
+            concurrency,
+        }
+    }
+}
+
+/// What a single tenant saw over a run. Requests that timed out count as taking the full timeout, since that is
+/// how long the client waited; unroutable and throttled requests are left out of the percentiles.
+#[derive(Debug, Clone, Default)]
+pub struct TenantLatency {
+    pub requests: usize,
+    pub completed: usize,
+    pub timed_out: usize,
+    pub unroutable: usize,
+    /// Requests the picker turned away because it had throttled the tenant.
+    pub throttled: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+/// The outcome of a run, per tenant.
+#[derive(Debug, Clone, Default)]
+pub struct Latencies {
+    pub tenants: BTreeMap<TenantId, TenantLatency>,
+}
+
+impl Latencies {
+    /// Tenants whose 99th percentile latency exceeded `limit`.
+    pub fn slow_tenants(&self, limit: Duration) -> Vec<TenantId> {
+        self.tenants
+            .iter()
+            .filter(|(_, l)| l.p99 > limit)
+            .map(|(&t, _)| t)
+            .collect()
+    }
+}
+
+/// Plays traffic from a set of tenants against a fleet of servers through a picker.
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    duration: Duration,
+    timeout: Duration,
+    seed: u64,
+    tenants: BTreeMap<TenantId, Arrival>,
+    servers: BTreeMap<BackendId, Server>,
+}
+
+impl Simulator {
+    /// Tenants send requests for `duration`; requests still outstanding at the end are played out. Requests time out
+    /// after one second unless `with_timeout` says otherwise.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            timeout: Duration::from_secs(1),
+            seed: 42,
+            tenants: BTreeMap::new(),
+            servers: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Seed for arrivals and service times.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Adds a tenant, or replaces how an existing one sends requests.
+    pub fn tenant(mut self, id: TenantId, arrival: Arrival) -> Self {
+        self.tenants.insert(id, arrival);
+        self
+    }
+
+    /// Adds a backend, or replaces an existing one.
+    pub fn backend(mut self, id: BackendId, server: Server) -> Self {
+        self.servers.insert(id, server);
+        self
+    }
+
+    /// Registers every backend with `picker` as up and plays the traffic through it. The picker hears about every
+    /// request leaving a backend through `on_request_complete`, about the service time of every request a backend
+    /// finishes, in seconds, through `report_load`, and about every request that succeeds or times out through
+    /// `report_outcome`.
+    pub fn run<P: Picker + ?Sized>(&self, picker: &mut P) -> Latencies {
+        for &b in self.servers.keys() {
+            picker.register(b, Health::Up);
+        }
+        let mut run = Run {
+            sim: self,
+            picker,
+            prng: SmallRng::seed_from_u64(self.seed),
+            events: BinaryHeap::new(),
+            seq: 0,
+            requests: Vec::new(),
+            backends: self
+                .servers
+                .keys()
+                .map(|&b| (b, Queue::default()))
+                .collect(),
+            samples: self.tenants.keys().map(|&t| (t, Vec::new())).collect(),
+            stats: self
+                .tenants
+                .keys()
+                .map(|&t| (t, TenantLatency::default()))
+                .collect(),
+        };
+        for (&t, arrival) in &self.tenants {
+            if let Some(at) = arrival.next(Duration::ZERO, self.duration, &mut run.prng) {
+                run.schedule(at, Event::Arrive(t));
+            }
+        }
+        while let Some(Reverse((now, _, event))) = run.events.pop() {
+            run.handle(now, event);
+        }
+
+        let mut stats = run.stats;
+        for (t, mut samples) in run.samples {
+            samples.sort_unstable();
+            let percentile = |q: f64| -> Duration {
+                if samples.is_empty() {
+                    return Duration::ZERO;
+                }
+                // Nearest rank.
+                let rank = (q * samples.len() as f64).ceil() as usize;
+                samples[rank.clamp(1, samples.len()) - 1]
+            };
+            let s = stats.get_mut(&t).unwrap();
+            s.p50 = percentile(0.5);
+            s.p90 = percentile(0.9);
+            s.p99 = percentile(0.99);
+            s.p999 = percentile(0.999);
+            s.max = percentile(1.0);
+        }
+        Latencies { tenants: stats }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    Arrive(TenantId),
+    /// A backend finished serving the request with this index.
+    Finish(usize),
+    /// The client gives up on the request with this index.
+    Expire(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Queued,
+    Serving,
+    Done,
+    TimedOut,
+}
+
+struct Request {
+    tenant: TenantId,
+    backend: BackendId,
+    sent: Duration,
+    /// When the backend started serving it.
+    started: Duration,
+    state: State,
+}
+
+#[derive(Default)]
+struct Queue {
+    serving: usize,
+    waiting: VecDeque<usize>,
+}
+
+struct Run<'a, P: ?Sized> {
+    sim: &'a Simulator,
+    picker: &'a mut P,
+    prng: SmallRng,
+    /// Pending events, earliest first. Ties go to whichever was scheduled first.
+    events: BinaryHeap<Reverse<(Duration, u64, Event)>>,
+    seq: u64,
+    requests: Vec<Request>,
+    backends: BTreeMap<BackendId, Queue>,
+    samples: BTreeMap<TenantId, Vec<Duration>>,
+    stats: BTreeMap<TenantId, TenantLatency>,
+}
+
+impl<P: Picker + ?Sized> Run<'_, P> {
+    fn schedule(&mut self, at: Duration, event: Event) {
+        self.seq += 1;
+        self.events.push(Reverse((at, self.seq, event)));
+    }
+
+    fn handle(&mut self, now: Duration, event: Event) {
+        match event {
+            Event::Arrive(t) => {
+                let arrival = &self.sim.tenants[&t];
+                if let Some(at) = arrival.next(now, self.sim.duration, &mut self.prng) {
+                    self.schedule(at, Event::Arrive(t));
+                }
+                self.arrive(now, t);
+            }
+            Event::Finish(r) => self.finish(now, r),
+            Event::Expire(r) => self.expire(r),
+        }
+    }
+
+    fn arrive(&mut self, now: Duration, tenant: TenantId) {
+        let stats = self.stats.get_mut(&tenant).unwrap();
+        stats.requests += 1;
+        let backend = match self.picker.pick(tenant) {
+            Ok(pick) if self.backends.contains_key(&pick.backend) => pick.backend,
+            Err(PickError::TenantThrottled) => {
+                stats.throttled += 1;
+                return;
+            }
+            _ => {
+                stats.unroutable += 1;
+                return;
+            }
+        };
+
+        let r = self.requests.len();
+        self.requests.push(Request {
+            tenant,
+            backend,
+            sent: now,
+            started: now,
+            state: State::Queued,
+        });
+        self.schedule(now + self.sim.timeout, Event::Expire(r));
+        let queue = self.backends.get_mut(&backend).unwrap();
+        if queue.serving < self.sim.servers[&backend].concurrency {
+            self.start(now, r);
+        } else {
+            queue.waiting.push_back(r);
+        }
+    }
+
+    fn start(&mut self, now: Duration, r: usize) {
+        let backend = self.requests[r].backend;
+        self.requests[r].state = State::Serving;
+        self.requests[r].started = now;
+        self.backends.get_mut(&backend).unwrap().serving += 1;
+        let service = self.sim.servers[&backend].service.sample(&mut self.prng);
+        self.schedule(now + service, Event::Finish(r));
+    }
+
+    fn finish(&mut self, now: Duration, r: usize) {
+        let request = &mut self.requests[r];
+        let (tenant, backend) = (request.tenant, request.backend);
+        let latency = now - request.sent;
+        if request.state == State::Serving {
+            request.state = State::Done;
+            self.stats.get_mut(&tenant).unwrap().completed += 1;
+            self.samples.get_mut(&tenant).unwrap().push(latency);
+            self.picker
+                .report_outcome(tenant, backend, Outcome::Success);
+        }
+        // A request that timed out while being served still held its slot until now.
+        self.picker.on_request_complete(backend);
+        let queue = self.backends.get_mut(&backend).unwrap();
+        queue.serving -= 1;
+        // Requests that timed out while waiting have already left the queue as far as the picker is concerned.
+        while let Some(next) = queue.waiting.pop_front() {
+            if self.requests[next].state == State::Queued {
+                self.start(now, next);
+                break;
+            }
+        }
+        let service = now - self.requests[r].started;
+        self.picker.report_load(backend, service.as_secs_f64());
+    }
+
+    fn expire(&mut self, r: usize) {
+        let request = &mut self.requests[r];
+        let (tenant, backend, state) = (request.tenant, request.backend, request.state);
+        if matches!(state, State::Done | State::TimedOut) {
+            return;
+        }
+        request.state = State::TimedOut;
+        self.stats.get_mut(&tenant).unwrap().timed_out += 1;
+        self.samples
+            .get_mut(&tenant)
+            .unwrap()
+            .push(self.sim.timeout);
+        self.picker
+            .report_outcome(tenant, backend, Outcome::Timeout);
+        if state == State::Queued {
+            // The backend drops it without ever starting on it.
+            self.picker.on_request_complete(backend);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    hash_fn::{HashFn, SipHash13},
+    spread_shard, Backend, BackendId, Health, Pick, PickError, PickResult, Picker, TenantId,
+    ZoneId,
+};
+
+/// Rendezvous shuffle sharding that spreads every shard across zones.
+///
+/// Each zone ranks its backends by weighted rendezvous score for the tenant, and the shard is built by taking
+/// the best remaining backend from each zone in turn. With `z` zones every zone contributes either
+/// `⌊k/z⌋` or `⌈k/z⌉` members, so as long as `k >= z` losing a whole zone never empties a shard. The zones
+/// that contribute the extra members are chosen per tenant, so the load stays even.
+pub struct ZonedShuffle<R = SmallRng, H = SipHash13> {
+    backends: Vec<(ZoneId, Backend)>,
+    shard_size: usize,
+    shard_seed: u64,
+    hasher: H,
+    prng: R,
+}
+
+impl<R: Rng, H: HashFn + Default> ZonedShuffle<R, H> {
+    /// Like `Picker::new`, but `prng` chooses among the healthy members of the shard.
+    pub fn with_rng(shard_size: usize, prng: R) -> Self {
+        Self {
+            backends: Vec::new(),
+            shard_size: shard_size.max(1),
+            shard_seed: 0,
+            hasher: H::default(),
+            prng,
+        }
+    }
+
+    /// Replaces the hash function that places backends, e.g. to use a keyed or seeded variant.
+    pub fn with_hasher(mut self, hasher: H) -> Self {
+        self.backends = self
+            .backends
+            .iter()
+            .map(|&(zone, b)| (zone, b.hashed_with(&hasher)))
+            .collect();
+        self.hasher = hasher;
+        self
+    }
+
+    /// Mixes `shard_seed` into the tenant's rendezvous hash, for both the zone order and the ranking within
+    /// each zone.
+    pub fn with_shard_seed(mut self, shard_seed: u64) -> Self {
+        self.shard_seed = shard_seed;
+        self
+    }
+
+    pub fn register_in_zone(&mut self, id: BackendId, zone: ZoneId, health: Health, weight: u32) {
+        if let Some((existing_zone, existing)) = self.backends.iter_mut().find(|(_, b)| b.id == id)
+        {
+            *existing_zone = zone;
+            existing.health = health;
+            existing.weight = weight;
+        } else {
+            self.backends.push((
+                zone,
+                Backend::new(id, health, weight).hashed_with(&self.hasher),
+            ));
+        }
+    }
+
+    /// The tenant's shard, spread across zones, out of the backends that `eligible` accepts.
+    fn spread(&self, id: TenantId, eligible: impl Fn(&Backend) -> bool) -> Vec<Backend> {
+        let th = self.hasher.hash_u64(id.0) ^ self.shard_seed;
+        spread_shard(
+            &self.backends,
+            th,
+            |zone| self.hasher.hash_u64(zone.0),
+            self.shard_size,
+            eligible,
+        )
+        .into_iter()
+        .map(|(_, b)| b)
+        .collect()
+    }
+
+    fn shard_members(&self, id: TenantId) -> Vec<Backend> {
+        // Like `RendevouzShuffle`, draining backends leave the shard entirely. Unlike it, we hold on to them so
+        // that they keep their zone when they come back.
+        self.spread(id, |b| b.health != Health::Draining)
+    }
+
+    /// Whether `b` only joined the tenant's shard to stand in for a draining member.
+    fn outside_primary_shard(&self, id: TenantId, b: BackendId) -> bool {
+        self.backends
+            .iter()
+            .any(|(_, b)| b.health == Health::Draining)
+            && !self
+                .spread(id, |_| true)
+                .iter()
+                .any(|primary| primary.id == b)
+    }
+}
+
+impl<R: Rng + SeedableRng, H: HashFn + Default> Picker for ZonedShuffle<R, H> {
+    fn new(shard_size: usize) -> Self {
+        Self::with_rng(shard_size, R::seed_from_u64(42))
+    }
+    /// Backends registered without a zone keep the zone they already had, or join `ZoneId::default()`.
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        let zone = self
+            .backends
+            .iter()
+            .find(|(_, b)| b.id == id)
+            .map(|&(zone, _)| zone)
+            .unwrap_or_default();
+        self.register_in_zone(id, zone, health, weight);
+    }
+
+    fn unregister(&mut self, id: BackendId) {
+        self.backends.retain(|(_, b)| b.id != id);
+    }
+
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        if self.backends.is_empty() {
+            return Err(PickError::NoBackends);
+        }
+        let healthy: Vec<Backend> = self
+            .shard_members(id)
+            .into_iter()
+            .filter(|b| b.health == Health::Up)
+            .collect();
+        let choice = healthy
+            .choose(&mut self.prng)
+            .ok_or(PickError::ShardUnavailable)?;
+        Ok(Pick::new(
+            choice.id,
+            self.outside_primary_shard(id, choice.id),
+        ))
+    }
+
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.shard_members(id)
+            .into_iter()
+            .map(|b| (b.id, b.health))
+            .collect()
+    }
+
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.outside_primary_shard(id, backend)
+    }
+}
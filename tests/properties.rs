@@ -0,0 +1,1326 @@
+//! Properties every picker must have, and the golden vectors that pin placements down.
+
+use anyhow::bail;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, BTreeSet},
+    rc::Rc,
+    time::{Duration, SystemTime},
+};
+
+use flexss::{
+    block_picker::{BlockId, BlockPicker},
+    bounded_load::BoundedLoadRendezvous,
+    cell::{CellId, CellRouter},
+    circuit_breaker::CircuitBreaker,
+    concurrent::ConcurrentRendevouzShuffle,
+    drain_aware_shuffle::DrainAwareShuffle,
+    hash_fn::{HashFn, SipHash13, WyHash, XxHash3},
+    jump_hash::{JumpHash, JumpShuffle},
+    least_loaded::LeastLoaded,
+    maglev::{Maglev, MaglevShuffle},
+    multi_probe::{MultiProbe, MultiProbeShuffle},
+    naive_shuffle::NaiveShuffle,
+    outlier::{OutlierDetection, DEFAULT_CONSECUTIVE_ERRORS},
+    overrides::Overrides,
+    rendevouz::Rendevouz,
+    rendevouz_shuffle::RendevouzShuffle,
+    ring::{Ring, RingShuffle},
+    simulator::{Arrival, Server, ServiceTime, Simulator},
+    zoned_shuffle::ZonedShuffle,
+    BackendId, Health, Outcome, Pick, PickError, PickResult, Picker, RoundRobin, ShardSize,
+    TenantId, ZoneId,
+};
+
+#[test]
+fn placements_match_golden_vectors() {
+    // Placements must never change underneath a running fleet, whatever the toolchain.
+    // The SipHash13 rendezvous shards and picks are the ones `DefaultHasher` gave
+    // before pickers took a `HashFn`; the naive shards moved once, off `SmallRng`.
+    golden_vectors::<SipHash13>(
+        [0xbd60acb658c79e45, 0x1e9f734161d62dd9, 0xfb058313e6201d48],
+        [
+            ([20, 2, 1, 23, 21], 28),
+            ([3, 19, 2, 28, 18], 2),
+            ([24, 21, 10, 16, 26], 3),
+            ([23, 11, 8, 14, 20], 14),
+        ],
+        [
+            [6, 11, 4, 19, 9],
+            [14, 15, 25, 16, 21],
+            [11, 28, 7, 19, 14],
+            [11, 0, 29, 12, 15],
+        ],
+    )
+    .unwrap();
+    golden_vectors::<XxHash3>(
+        [0xc77b3abb6f87acd9, 0x2fbc593564db792e, 0x07ee86c281446bef],
+        [
+            ([28, 4, 8, 17, 9], 11),
+            ([12, 3, 5, 22, 7], 23),
+            ([10, 27, 1, 2, 17], 4),
+            ([1, 22, 19, 23, 8], 8),
+        ],
+        [
+            [15, 22, 5, 4, 29],
+            [28, 24, 8, 16, 11],
+            [3, 18, 7, 2, 26],
+            [28, 5, 29, 21, 2],
+        ],
+    )
+    .unwrap();
+    golden_vectors::<WyHash>(
+        [0xad8f7077779c7c69, 0x59826f62ca1d5aa6, 0xd47c63bc856857bb],
+        [
+            ([7, 28, 14, 12, 16], 14),
+            ([27, 0, 24, 7, 11], 28),
+            ([24, 17, 23, 10, 1], 5),
+            ([0, 21, 5, 28, 29], 12),
+        ],
+        [
+            [16, 15, 7, 28, 3],
+            [5, 24, 13, 26, 0],
+            [3, 6, 16, 26, 14],
+            [4, 9, 20, 29, 5],
+        ],
+    )
+    .unwrap();
+}
+
+#[test]
+fn load_follows_weight() {
+    weighted_load_distribution::<RoundRobin>().unwrap();
+    weighted_load_distribution::<NaiveShuffle>().unwrap();
+    weighted_load_distribution::<DrainAwareShuffle>().unwrap();
+    weighted_load_distribution::<BlockPicker>().unwrap();
+    weighted_load_distribution::<Rendevouz>().unwrap();
+    weighted_load_distribution::<Maglev>().unwrap();
+    weighted_load_distribution::<JumpHash>().unwrap();
+    weighted_load_distribution::<MultiProbe>().unwrap();
+    weighted_load_distribution::<Ring>().unwrap();
+    weighted_load_distribution::<BoundedLoadRendezvous>().unwrap();
+    weighted_load_distribution::<RendevouzShuffle>().unwrap();
+    weighted_load_distribution::<MaglevShuffle>().unwrap();
+    weighted_load_distribution::<JumpShuffle>().unwrap();
+    weighted_load_distribution::<MultiProbeShuffle>().unwrap();
+    weighted_load_distribution::<RingShuffle>().unwrap();
+    weighted_load_distribution::<ConcurrentRendevouzShuffle>().unwrap();
+    weighted_load_distribution::<ZonedShuffle>().unwrap();
+}
+
+#[test]
+fn growing_a_backend_only_moves_tenants_onto_it() {
+    // Weighted rendezvous hashing and weighted shuffles only move tenants
+    // onto the backend that gained capacity.
+    weight_increase_blast_radius::<NaiveShuffle>().unwrap();
+    weight_increase_blast_radius::<DrainAwareShuffle>().unwrap();
+    weight_increase_blast_radius::<Rendevouz>().unwrap();
+    // Maglev only promises near-minimal disruption: when one backend claims
+    // more slots, the slots the others fall back to shift around too.
+    assert!(weight_increase_blast_radius::<Maglev>().is_err());
+    weight_increase_blast_radius::<JumpHash>().unwrap();
+    weight_increase_blast_radius::<MultiProbe>().unwrap();
+    weight_increase_blast_radius::<Ring>().unwrap();
+    weight_increase_blast_radius::<RendevouzShuffle>().unwrap();
+    assert!(weight_increase_blast_radius::<MaglevShuffle>().is_err());
+    // Jump shards are built probe by probe, so when a grown backend absorbs a
+    // probe that used to find someone else, a later probe brings in a stranger.
+    assert!(weight_increase_blast_radius::<JumpShuffle>().is_err());
+    weight_increase_blast_radius::<MultiProbeShuffle>().unwrap();
+    weight_increase_blast_radius::<RingShuffle>().unwrap();
+    weight_increase_blast_radius::<ConcurrentRendevouzShuffle>().unwrap();
+    weight_increase_blast_radius::<ZonedShuffle>().unwrap();
+    // Blocks rank their members by rendezvous score, so a heavier backend
+    // only takes tenants from the rest of its own block.
+    weight_increase_blast_radius::<BlockPicker>().unwrap();
+}
+
+#[test]
+fn zone_outages() {
+    // Zone-oblivious pickers occasionally put a tenant's whole shard in one zone.
+    assert!(zone_outage::<NaiveShuffle>(|p, b, _, h| p.register(b, h)).is_err());
+    assert!(zone_outage::<RendevouzShuffle>(|p, b, _, h| p.register(b, h)).is_err());
+    assert!(zone_outage::<ConcurrentRendevouzShuffle>(|p, b, _, h| p.register(b, h)).is_err());
+    assert!(zone_outage::<JumpShuffle>(|p, b, _, h| p.register(b, h)).is_err());
+    assert!(zone_outage::<MultiProbeShuffle>(|p, b, _, h| p.register(b, h)).is_err());
+    assert!(zone_outage::<RingShuffle>(|p, b, _, h| p.register(b, h)).is_err());
+    zone_outage::<ZonedShuffle>(|p, b, z, h| p.register_in_zone(b, z, h, 1)).unwrap();
+}
+
+#[test]
+fn group_deploys() {
+    // Deploying a group at a time takes a tenant's whole shard out of service whenever the shard happens to lie
+    // within one group, unless the picker knows the groups.
+    assert!(deploy_groups::<NaiveShuffle>(|p, b, _, h| p.register(b, h)).is_err());
+    assert!(deploy_groups::<BlockPicker>(|p, b, _, h| p.register(b, h)).is_err());
+    deploy_groups::<BlockPicker>(|p, b, g, h| p.register_in_block(b, BlockId(g), h, 1)).unwrap();
+    deploy_groups::<ZonedShuffle>(|p, b, g, h| p.register_in_zone(b, ZoneId(g), h, 1)).unwrap();
+}
+
+#[test]
+fn shards_grow_by_prefix() {
+    // Tenants can have shards of their own size, and growing one only adds backends to it.
+    shard_prefixes::<NaiveShuffle>(NaiveShuffle::with_shard_size).unwrap();
+    shard_prefixes::<DrainAwareShuffle>(DrainAwareShuffle::with_shard_size).unwrap();
+    shard_prefixes::<RendevouzShuffle>(RendevouzShuffle::with_shard_size).unwrap();
+}
+
+#[test]
+fn cell_routing() {
+    cells().unwrap();
+}
+
+#[test]
+fn override_tables() {
+    overrides().unwrap();
+}
+
+#[test]
+fn breakers_blame_the_reporting_tenant() {
+    breaker_blame().unwrap();
+}
+
+#[test]
+fn quarantines_run_out_by_time() {
+    quarantine_backoff().unwrap();
+}
+
+#[test]
+fn ejections_back_off_by_time() {
+    ejection_backoff().unwrap();
+}
+
+#[test]
+fn pick_errors_tell_empty_and_dead_fleets_apart() {
+    pick_errors::<RoundRobin>().unwrap();
+    pick_errors::<NaiveShuffle>().unwrap();
+    pick_errors::<DrainAwareShuffle>().unwrap();
+    pick_errors::<BlockPicker>().unwrap();
+    pick_errors::<Rendevouz>().unwrap();
+    pick_errors::<Maglev>().unwrap();
+    pick_errors::<JumpHash>().unwrap();
+    pick_errors::<MultiProbe>().unwrap();
+    pick_errors::<Ring>().unwrap();
+    pick_errors::<BoundedLoadRendezvous>().unwrap();
+    pick_errors::<RendevouzShuffle>().unwrap();
+    pick_errors::<MaglevShuffle>().unwrap();
+    pick_errors::<JumpShuffle>().unwrap();
+    pick_errors::<MultiProbeShuffle>().unwrap();
+    pick_errors::<RingShuffle>().unwrap();
+    pick_errors::<LeastLoaded<RendevouzShuffle>>().unwrap();
+    pick_errors::<ConcurrentRendevouzShuffle>().unwrap();
+    pick_errors::<ZonedShuffle>().unwrap();
+    pick_errors::<CellRouter<RendevouzShuffle>>().unwrap();
+    pick_errors::<Overrides<NaiveShuffle>>().unwrap();
+    pick_errors::<OutlierDetection<NaiveShuffle>>().unwrap();
+    pick_errors::<CircuitBreaker<NaiveShuffle>>().unwrap();
+}
+
+#[test]
+fn maglev_tables_smaller_than_the_fleet() {
+    small_maglev_tables().unwrap();
+}
+
+#[test]
+fn fallbacks_are_reported() {
+    // Pickers that rank the whole fleet move on to the next backend when the
+    // first is down, and say so.
+    assert_eq!(fallbacks::<Rendevouz>(Health::Down), 1_000);
+    assert_eq!(fallbacks::<JumpHash>(Health::Down), 1_000);
+    assert_eq!(fallbacks::<MultiProbe>(Health::Down), 1_000);
+    assert_eq!(fallbacks::<Ring>(Health::Down), 1_000);
+    assert_eq!(fallbacks::<BoundedLoadRendezvous>(Health::Down), 1_000);
+    // Shuffle sharding stays inside the shard,
+    assert_eq!(fallbacks::<NaiveShuffle>(Health::Down), 0);
+    assert_eq!(fallbacks::<RendevouzShuffle>(Health::Down), 0);
+    assert_eq!(fallbacks::<RingShuffle>(Health::Down), 0);
+    assert_eq!(fallbacks::<DrainAwareShuffle>(Health::Down), 0);
+    // unless it replaces draining members with backends from outside.
+    assert!(fallbacks::<DrainAwareShuffle>(Health::Draining) > 0);
+    // Balancing on load keeps the inner picker's word for it.
+    assert!(fallbacks::<LeastLoaded<DrainAwareShuffle>>(Health::Draining) > 0);
+    assert_eq!(fallbacks::<LeastLoaded<Rendevouz>>(Health::Down), 1_000);
+    // Maglev hands a slot to another backend when its owner goes down, and
+    // flags every tenant that moved.
+    let mut maglev = <Maglev>::new(1);
+    for i in 0..10 {
+        maglev.register(BackendId(i), Health::Up);
+    }
+    let before: Vec<_> = (0..100)
+        .map(|t| maglev.pick(TenantId(t)).unwrap().backend)
+        .collect();
+    maglev.register(BackendId(0), Health::Down);
+    for (t, was) in before.into_iter().enumerate() {
+        let pick = maglev.pick(TenantId(t as u64)).unwrap();
+        assert_eq!(pick.fallback, pick.backend != was);
+        assert!(pick.fallback || was != BackendId(0));
+    }
+    // Every sharded picker flags exactly the picks that leave the primary shard.
+    for health in [Health::Down, Health::Draining] {
+        fallbacks_leave_shard::<NaiveShuffle>(health).unwrap();
+        fallbacks_leave_shard::<DrainAwareShuffle>(health).unwrap();
+        fallbacks_leave_shard::<BlockPicker>(health).unwrap();
+        fallbacks_leave_shard::<RendevouzShuffle>(health).unwrap();
+        fallbacks_leave_shard::<MaglevShuffle>(health).unwrap();
+        fallbacks_leave_shard::<JumpShuffle>(health).unwrap();
+        fallbacks_leave_shard::<MultiProbeShuffle>(health).unwrap();
+        fallbacks_leave_shard::<RingShuffle>(health).unwrap();
+        fallbacks_leave_shard::<ConcurrentRendevouzShuffle>(health).unwrap();
+        fallbacks_leave_shard::<ZonedShuffle>(health).unwrap();
+    }
+    // Replacing a draining member takes some picks outside the shard.
+    assert!(fallbacks_leave_shard::<RendevouzShuffle>(Health::Draining).unwrap() > 0);
+    assert!(fallbacks_leave_shard::<BlockPicker>(Health::Draining).unwrap() > 0);
+    assert!(fallbacks_leave_shard::<MaglevShuffle>(Health::Draining).unwrap() > 0);
+    assert!(fallbacks_leave_shard::<JumpShuffle>(Health::Draining).unwrap() > 0);
+    assert!(fallbacks_leave_shard::<MultiProbeShuffle>(Health::Draining).unwrap() > 0);
+    assert!(fallbacks_leave_shard::<RingShuffle>(Health::Draining).unwrap() > 0);
+    assert!(fallbacks_leave_shard::<ConcurrentRendevouzShuffle>(Health::Draining).unwrap() > 0);
+    assert!(fallbacks_leave_shard::<ZonedShuffle>(Health::Draining).unwrap() > 0);
+}
+
+#[test]
+fn picks_stay_within_shards() {
+    picks_stay_in_shard::<RoundRobin>().unwrap();
+    picks_stay_in_shard::<NaiveShuffle>().unwrap();
+    picks_stay_in_shard::<DrainAwareShuffle>().unwrap();
+    picks_stay_in_shard::<BlockPicker>().unwrap();
+    picks_stay_in_shard::<Rendevouz>().unwrap();
+    picks_stay_in_shard::<Maglev>().unwrap();
+    picks_stay_in_shard::<JumpHash>().unwrap();
+    picks_stay_in_shard::<MultiProbe>().unwrap();
+    picks_stay_in_shard::<Ring>().unwrap();
+    picks_stay_in_shard::<BoundedLoadRendezvous>().unwrap();
+    picks_stay_in_shard::<RendevouzShuffle>().unwrap();
+    picks_stay_in_shard::<MaglevShuffle>().unwrap();
+    picks_stay_in_shard::<JumpShuffle>().unwrap();
+    picks_stay_in_shard::<MultiProbeShuffle>().unwrap();
+    picks_stay_in_shard::<RingShuffle>().unwrap();
+    picks_stay_in_shard::<LeastLoaded<RendevouzShuffle>>().unwrap();
+    picks_stay_in_shard::<ConcurrentRendevouzShuffle>().unwrap();
+    picks_stay_in_shard::<ZonedShuffle>().unwrap();
+    picks_stay_in_shard::<CellRouter<RendevouzShuffle>>().unwrap();
+}
+
+#[test]
+fn degraded_fleet_rules() {
+    // A shard size of 0 counts as 1 everywhere.
+    for shard_size in [0, 3] {
+        degraded_fleets::<RoundRobin>(shard_size).unwrap();
+        degraded_fleets::<NaiveShuffle>(shard_size).unwrap();
+        degraded_fleets::<DrainAwareShuffle>(shard_size).unwrap();
+        degraded_fleets::<BlockPicker>(shard_size).unwrap();
+        degraded_fleets::<Rendevouz>(shard_size).unwrap();
+        degraded_fleets::<Maglev>(shard_size).unwrap();
+        degraded_fleets::<JumpHash>(shard_size).unwrap();
+        degraded_fleets::<MultiProbe>(shard_size).unwrap();
+        degraded_fleets::<Ring>(shard_size).unwrap();
+        degraded_fleets::<BoundedLoadRendezvous>(shard_size).unwrap();
+        degraded_fleets::<RendevouzShuffle>(shard_size).unwrap();
+        degraded_fleets::<MaglevShuffle>(shard_size).unwrap();
+        degraded_fleets::<JumpShuffle>(shard_size).unwrap();
+        degraded_fleets::<MultiProbeShuffle>(shard_size).unwrap();
+        degraded_fleets::<RingShuffle>(shard_size).unwrap();
+        degraded_fleets::<LeastLoaded<RendevouzShuffle>>(shard_size).unwrap();
+        degraded_fleets::<ConcurrentRendevouzShuffle>(shard_size).unwrap();
+        degraded_fleets::<ZonedShuffle>(shard_size).unwrap();
+        degraded_fleets::<CellRouter<RendevouzShuffle>>(shard_size).unwrap();
+        degraded_fleets::<Overrides<NaiveShuffle>>(shard_size).unwrap();
+        degraded_fleets::<OutlierDetection<NaiveShuffle>>(shard_size).unwrap();
+        degraded_fleets::<CircuitBreaker<NaiveShuffle>>(shard_size).unwrap();
+    }
+}
+
+#[test]
+fn load_reports_steer_around_slow_backends() {
+    // Only pickers that listen to load reports steer around a slow backend.
+    assert!(slow_backend::<RendevouzShuffle>().is_err());
+    slow_backend::<LeastLoaded<NaiveShuffle>>().unwrap();
+    slow_backend::<LeastLoaded<RendevouzShuffle>>().unwrap();
+}
+
+#[test]
+fn balancing_keeps_out_of_the_inner_picker() {
+    // The inner picker's own picks have side effects, so power-of-two-choices
+    // draws its candidates from the shard instead.
+    balanced_picks().unwrap();
+}
+
+#[test]
+fn slow_backend_tail_latencies() {
+    // Played out over time, a slow backend queues up until its requests time
+    // out. Round-robin spreads that to every tenant's tail, sharding confines
+    // it to the tenants whose shard includes the slow backend,
+    assert!(slow_backend_tail_latency::<RoundRobin>().is_err());
+    slow_backend_tail_latency::<NaiveShuffle>().unwrap();
+    slow_backend_tail_latency::<RendevouzShuffle>().unwrap();
+    // and steering by load keeps even those tenants mostly off it.
+    slow_backend_tail_latency::<LeastLoaded<RendevouzShuffle>>().unwrap();
+}
+
+#[test]
+fn noisy_neighbours() {
+    // A tenant bursting far past its share swamps whatever it is routed to.
+    assert!(noisy_neighbour::<RoundRobin>().is_err());
+    noisy_neighbour::<NaiveShuffle>().unwrap();
+    noisy_neighbour::<RendevouzShuffle>().unwrap();
+    noisy_neighbour::<BlockPicker>().unwrap();
+    // Tenants that share a backend with it can still route around the worst of it.
+    noisy_neighbour::<LeastLoaded<RendevouzShuffle>>().unwrap();
+}
+
+#[test]
+fn seeds_replay_choices() {
+    seeded_randomness(|seed, shard_seed| {
+        <NaiveShuffle>::with_rng(5, SmallRng::seed_from_u64(seed)).with_shard_seed(shard_seed)
+    })
+    .unwrap();
+    seeded_randomness(|seed, shard_seed| {
+        <DrainAwareShuffle>::with_rng(5, SmallRng::seed_from_u64(seed)).with_shard_seed(shard_seed)
+    })
+    .unwrap();
+    seeded_randomness(|seed, shard_seed| {
+        <BlockPicker>::with_rng(5, SmallRng::seed_from_u64(seed)).with_shard_seed(shard_seed)
+    })
+    .unwrap();
+    seeded_randomness(|seed, shard_seed| {
+        <RendevouzShuffle>::with_rng(5, SmallRng::seed_from_u64(seed)).with_shard_seed(shard_seed)
+    })
+    .unwrap();
+    seeded_randomness(|seed, shard_seed| {
+        <ConcurrentRendevouzShuffle>::with_rng(5, SmallRng::seed_from_u64(seed))
+            .with_shard_seed(shard_seed)
+    })
+    .unwrap();
+    seeded_randomness(|seed, shard_seed| {
+        <ZonedShuffle>::with_rng(5, SmallRng::seed_from_u64(seed)).with_shard_seed(shard_seed)
+    })
+    .unwrap();
+}
+
+/// A `RendevouzShuffle` that counts how often it is asked to pick.
+struct CountedPicks {
+    inner: RendevouzShuffle,
+    picks: Rc<Cell<usize>>,
+}
+
+impl Picker for CountedPicks {
+    fn new(shard_size: usize) -> Self {
+        Self {
+            inner: RendevouzShuffle::new(shard_size),
+            picks: Rc::default(),
+        }
+    }
+    fn register_weighted(&mut self, id: BackendId, health: Health, weight: u32) {
+        self.inner.register_weighted(id, health, weight);
+    }
+    fn unregister(&mut self, id: BackendId) {
+        self.inner.unregister(id);
+    }
+    fn pick(&mut self, id: TenantId) -> PickResult {
+        self.picks.set(self.picks.get() + 1);
+        self.inner.pick(id)
+    }
+    fn shard(&self, id: TenantId) -> Vec<(BackendId, Health)> {
+        self.inner.shard(id)
+    }
+    fn falls_back(&self, id: TenantId, backend: BackendId) -> bool {
+        self.inner.falls_back(id, backend)
+    }
+}
+
+#[derive(Default)]
+struct Simulation {
+    backends: BTreeMap<BackendId, Health>,
+}
+
+/// `RendevouzShuffle` shard (k = 5) and the backend `Rendevouz` routes to, and the shard (k = 5) that `NaiveShuffle`
+/// and `DrainAwareShuffle` agree on.
+fn golden_vectors<H: HashFn + Default>(
+    hashes: [u64; 3],
+    placements: [([u64; 5], u64); 4],
+    shuffled: [[u64; 5]; 4],
+) -> anyhow::Result<()> {
+    let h = H::default();
+    let actual = [h.hash_u64(0), h.hash_u64(1), h.hash_pair(1, 2)];
+    if actual != hashes {
+        bail!("hash outputs changed: {actual:x?} != {hashes:x?}");
+    }
+
+    let mut shuffle: RendevouzShuffle<SmallRng, H> = Picker::new(5);
+    let mut rendevouz: Rendevouz<H> = Picker::new(5);
+    let mut naive: NaiveShuffle<SmallRng, H> = Picker::new(5);
+    let mut drain_aware: DrainAwareShuffle<SmallRng, H> = Picker::new(5);
+    for i in 0..30 {
+        shuffle.register(BackendId(i), Health::Up);
+        rendevouz.register(BackendId(i), Health::Up);
+        naive.register(BackendId(i), Health::Up);
+        drain_aware.register(BackendId(i), Health::Up);
+    }
+    for (tenant_id, (shard, choice)) in placements.into_iter().enumerate() {
+        let tenant_id = TenantId(tenant_id as u64);
+        let actual: Vec<u64> = shuffle.shard(tenant_id).iter().map(|(b, _)| b.0).collect();
+        if actual != shard {
+            bail!("shard for {tenant_id:?} moved from {shard:?} to {actual:?}");
+        }
+        let actual = rendevouz.pick(tenant_id).unwrap().backend.0;
+        if actual != choice {
+            bail!("rendevouz moved {tenant_id:?} from {choice} to {actual}");
+        }
+    }
+    for (tenant_id, shard) in shuffled.into_iter().enumerate() {
+        let tenant_id = TenantId(tenant_id as u64);
+        let ids =
+            |s: Vec<(BackendId, Health)>| -> Vec<u64> { s.iter().map(|(b, _)| b.0).collect() };
+        for (name, actual) in [
+            ("naive", ids(naive.shard(tenant_id))),
+            ("drain-aware", ids(drain_aware.shard(tenant_id))),
+        ] {
+            if actual != shard {
+                bail!("{name} shard for {tenant_id:?} moved from {shard:?} to {actual:?}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn weighted_load_distribution<P: Picker>() -> anyhow::Result<()> {
+    // A third of the fleet is three times as large as the rest, and should
+    // receive roughly three times as much traffic.
+    let mut p = P::new(5);
+    let backends: Vec<BackendId> = (0..30).map(BackendId).collect();
+    let weight = |b: BackendId| if b.0 < 10 { 3 } else { 1 };
+    for &b in &backends {
+        p.register_weighted(b, Health::Up, weight(b));
+    }
+
+    let mut tally: BTreeMap<BackendId, usize> = BTreeMap::new();
+    for tenant_id in 0..1_000 {
+        let tenant_id = TenantId(tenant_id);
+        for _ in 0..20 {
+            let b = p.pick(tenant_id).unwrap().backend;
+            *tally.entry(b).or_default() += 1;
+        }
+    }
+
+    let mean_load = |w: u32| {
+        let loads: Vec<usize> = backends
+            .iter()
+            .filter(|&&b| weight(b) == w)
+            .map(|b| tally.get(b).copied().unwrap_or_default())
+            .collect();
+        loads.iter().sum::<usize>() as f64 / loads.len() as f64
+    };
+    let ratio = mean_load(3) / mean_load(1);
+    if !(2.5..=3.5).contains(&ratio) {
+        bail!("weight-3 backends received {ratio:.2}x the load of weight-1 backends");
+    }
+    Ok(())
+}
+
+fn weight_increase_blast_radius<P: Picker>() -> anyhow::Result<()> {
+    let mut p = P::new(5);
+    let backends: Vec<BackendId> = (0..30).map(BackendId).collect();
+    for &b in &backends {
+        p.register(b, Health::Up);
+    }
+
+    let tenants: Vec<TenantId> = (0..500).map(TenantId).collect();
+    let touched = |p: &mut P| -> BTreeMap<TenantId, BTreeSet<BackendId>> {
+        tenants
+            .iter()
+            .map(|&t| (t, (0..100).map(|_| p.pick(t).unwrap().backend).collect()))
+            .collect()
+    };
+    let before = touched(&mut p);
+    // Double the capacity of a single backend. The only new backend any
+    // tenant should start talking to is that one.
+    let grown = backends[0];
+    p.register_weighted(grown, Health::Up, 2);
+    let after = touched(&mut p);
+
+    for t in &tenants {
+        if let Some(b) = after[t].difference(&before[t]).find(|&&b| b != grown) {
+            bail!("growing {grown:?} moved {t:?} onto {b:?}");
+        }
+    }
+    Ok(())
+}
+
+/// An empty fleet and a fleet with nothing up fail for different reasons.
+fn pick_errors<P: Picker>() -> anyhow::Result<()> {
+    let mut p = P::new(3);
+    let tenant_id = TenantId(0);
+    if p.pick(tenant_id) != Err(PickError::NoBackends) {
+        bail!("picking from an empty fleet gave {:?}", p.pick(tenant_id));
+    }
+    for i in 0..10 {
+        p.register(BackendId(i), Health::Down);
+    }
+    if p.pick(tenant_id) != Err(PickError::ShardUnavailable) {
+        bail!("picking from a dead fleet gave {:?}", p.pick(tenant_id));
+    }
+    Ok(())
+}
+
+/// A three-slot table over five backends leaves two of them without a slot. Picks must still come back, from the
+/// backends that have one.
+fn small_maglev_tables() -> anyhow::Result<()> {
+    let mut maglev = <Maglev>::new(1).with_table_size(3);
+    let mut shuffle = <MaglevShuffle>::new(5).with_table_size(3);
+    for i in 0..5 {
+        maglev.register(BackendId(i), Health::Up);
+        shuffle.register(BackendId(i), Health::Up);
+    }
+    for tenant_id in (0..100).map(TenantId) {
+        if maglev.shard(tenant_id).len() != 5 {
+            bail!("{tenant_id:?}: Maglev ranked {:?}", maglev.shard(tenant_id));
+        }
+        let shard = shuffle.shard(tenant_id);
+        if shard.len() > 3 {
+            bail!("{tenant_id:?}: a three-slot table gave shard {shard:?}");
+        }
+        maglev.pick(tenant_id)?;
+        let pick = shuffle.pick(tenant_id)?;
+        if !shard.iter().any(|&(id, _)| id == pick.backend) {
+            bail!("{tenant_id:?}: picked {pick:?} outside {shard:?}");
+        }
+    }
+    Ok(())
+}
+
+/// Takes the first member of every tenant's shard out of service with `health` and counts the picks, out of ten
+/// per tenant for 100 tenants, that report falling back outside the primary shard.
+fn fallbacks<P: Picker>(health: Health) -> usize {
+    let mut fallbacks = 0;
+    for tenant_id in 0..100 {
+        let tenant_id = TenantId(tenant_id);
+        let mut p = P::new(3);
+        for i in 0..10 {
+            p.register(BackendId(i), Health::Up);
+        }
+        let (first, _) = p.shard(tenant_id)[0];
+        p.register(first, health);
+        fallbacks += (0..10)
+            .filter(|_| p.pick(tenant_id).unwrap().fallback)
+            .count();
+    }
+    fallbacks
+}
+
+/// Like [`fallbacks`] over 30 tenants, but checks every pick against the tenant's shard from before the change: a
+/// pick must be flagged exactly when it lands outside that shard. Returns how many were.
+fn fallbacks_leave_shard<P: Picker>(health: Health) -> anyhow::Result<usize> {
+    let mut fallbacks = 0;
+    for tenant_id in 0..30 {
+        let tenant_id = TenantId(tenant_id);
+        let mut p = P::new(3);
+        for i in 0..10 {
+            p.register(BackendId(i), Health::Up);
+        }
+        let shard = p.shard(tenant_id);
+        let primary: BTreeSet<_> = shard.iter().map(|&(id, _)| id).collect();
+        p.register(shard[0].0, health);
+        for _ in 0..10 {
+            let pick = p.pick(tenant_id)?;
+            if pick.fallback == primary.contains(&pick.backend) {
+                bail!("{tenant_id:?}: {pick:?} against primary shard {primary:?}");
+            }
+            fallbacks += usize::from(pick.fallback);
+        }
+    }
+    Ok(fallbacks)
+}
+
+fn picks_stay_in_shard<P: Picker>() -> anyhow::Result<()> {
+    // A third of the fleet is down, so plenty of shards are partially unhealthy.
+    let mut p = P::new(5);
+    for i in 0..30 {
+        let h = if i % 3 == 0 { Health::Down } else { Health::Up };
+        p.register(BackendId(i), h);
+    }
+
+    for tenant_id in 0..500 {
+        let tenant_id = TenantId(tenant_id);
+        let shard = p.shard(tenant_id);
+        for _ in 0..100 {
+            let Ok(Pick { backend: b, .. }) = p.pick(tenant_id) else {
+                continue;
+            };
+            if !shard.contains(&(b, Health::Up)) {
+                bail!("tenant {tenant_id:?} got routed to {b:?}, which is not a healthy member of its shard");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Property check of the degraded-mode rules on `Picker`. Random fleets of zero up to one more than a shard (all
+/// up, all draining, all down, or mixed) are built by registering a couple of extra backends and unregistering them
+/// again. Whatever the fleet, an empty one must fail with `NoBackends`, an all-up one must serve every tenant, and
+/// otherwise a tenant is served, by a healthy member of its shard, exactly when its shard has one.
+fn degraded_fleets<P: Picker>(shard_size: usize) -> anyhow::Result<()> {
+    let mut prng = SmallRng::seed_from_u64(21);
+    for trial in 0..200 {
+        // Fleets around the shard size, and for tiny shards some larger than that.
+        let fleet = prng.gen_range(0..=shard_size.max(3) + 1);
+        let mut health = || match trial % 4 {
+            0 => Health::Up,
+            1 => Health::Draining,
+            2 => Health::Down,
+            _ => [Health::Up, Health::Draining, Health::Down][prng.gen_range(0..3)],
+        };
+        let mut registered: Vec<(BackendId, Health)> = (0..fleet as u64 + 2)
+            .map(|i| (BackendId(i), health()))
+            .collect();
+        let mut p = P::new(shard_size);
+        for &(b, h) in &registered {
+            p.register(b, h);
+        }
+        for _ in 0..2 {
+            let (b, _) = registered.remove(prng.gen_range(0..registered.len()));
+            p.unregister(b);
+        }
+
+        for tenant_id in 0..20 {
+            let tenant_id = TenantId(tenant_id);
+            let shard = p.shard(tenant_id);
+            let serviceable = shard.iter().any(|&(_, h)| h == Health::Up);
+            for _ in 0..5 {
+                let picked = p.pick(tenant_id);
+                match picked {
+                    _ if registered.is_empty() => {
+                        if picked != Err(PickError::NoBackends) || !shard.is_empty() {
+                            bail!("an empty fleet gave {picked:?} with shard {shard:?}");
+                        }
+                    }
+                    Ok(Pick { backend: b, .. }) if !shard.contains(&(b, Health::Up)) => {
+                        bail!("{registered:?} routed {tenant_id:?} to {b:?}, outside the healthy members of {shard:?}");
+                    }
+                    Ok(_) => {}
+                    Err(e) if serviceable || registered.iter().all(|&(_, h)| h == Health::Up) => {
+                        bail!("{registered:?} failed {tenant_id:?} with {e:?}, but its shard is {shard:?}");
+                    }
+                    Err(PickError::ShardUnavailable) => {}
+                    Err(e) => bail!("{registered:?} failed {tenant_id:?} with {e:?} instead of ShardUnavailable"),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn slow_backend<P: Picker>() -> anyhow::Result<()> {
+    let mut p = P::new(5);
+    let backends: Vec<BackendId> = (0..30).map(BackendId).collect();
+    for &b in &backends {
+        p.register(b, Health::Up);
+    }
+    // Every request takes 10ms, except on backend 0 where it takes 100ms.
+    let slow = backends[0];
+    let latency = |b: BackendId| if b == slow { 0.1 } else { 0.01 };
+
+    let mut tally: BTreeMap<BackendId, usize> = BTreeMap::new();
+    for tenant_id in 0..500 {
+        let tenant_id = TenantId(tenant_id);
+        for _ in 0..20 {
+            let b = p.pick(tenant_id).unwrap().backend;
+            p.report_load(b, latency(b));
+            p.on_request_complete(b);
+            *tally.entry(b).or_default() += 1;
+        }
+    }
+
+    let fair = 500 * 20 / backends.len();
+    let recv = tally.get(&slow).copied().unwrap_or_default();
+    if recv > fair / 2 {
+        bail!("{slow:?} is 10x slower but received {recv} requests (fair share is {fair})");
+    }
+    Ok(())
+}
+
+/// 20 backends serving four requests at once in 10ms on average, except backend 0 which takes 100ms, and 50
+/// tenants sending 80 requests per second each: about half the fleet's capacity, but twice what backend 0 can take.
+fn slow_backend_tail_latency<P: Picker>() -> anyhow::Result<()> {
+    let mut sim = Simulator::new(Duration::from_secs(10));
+    for b in 0..20 {
+        let mean = if b == 0 { 100 } else { 10 };
+        let server = Server::new(ServiceTime::Exponential(Duration::from_millis(mean)), 4);
+        sim = sim.backend(BackendId(b), server);
+    }
+    for t in 0..50 {
+        sim = sim.tenant(TenantId(t), Arrival::Poisson { rate: 80.0 });
+    }
+    let latencies = sim.run(&mut P::new(4));
+
+    let slow = latencies.slow_tenants(Duration::from_millis(100));
+    if slow.len() > latencies.tenants.len() / 2 {
+        bail!(
+            "{} of {} tenants saw a p99 over 100ms",
+            slow.len(),
+            latencies.tenants.len()
+        );
+    }
+    Ok(())
+}
+
+/// 20 backends serving two requests at once in 10ms on average, 100 tenants sending around 10 requests per second
+/// each, and tenant 0 sending 4,000 per second in bursts of one second out of every two.
+fn noisy_neighbour<P: Picker>() -> anyhow::Result<()> {
+    let mut sim = Simulator::new(Duration::from_secs(10));
+    for b in 0..20 {
+        let server = Server::new(ServiceTime::Exponential(Duration::from_millis(10)), 2);
+        sim = sim.backend(BackendId(b), server);
+    }
+    sim = sim.tenant(
+        TenantId(0),
+        Arrival::Bursty {
+            rate: 0.0,
+            burst_rate: 4_000.0,
+            on: Duration::from_secs(1),
+            off: Duration::from_secs(1),
+        },
+    );
+    for t in 1..100 {
+        let arrival = Arrival::Diurnal {
+            rate: 10.0,
+            amplitude: 0.5,
+            period: Duration::from_secs(10),
+        };
+        sim = sim.tenant(TenantId(t), arrival);
+    }
+    let latencies = sim.run(&mut P::new(4));
+
+    // Random shards of 4 out of 20 miss the noisy tenant's shard entirely about a third of the time.
+    let slow = latencies.slow_tenants(Duration::from_millis(100));
+    if slow.len() > latencies.tenants.len() * 3 / 4 {
+        bail!(
+            "{} of {} tenants saw a p99 over 100ms",
+            slow.len(),
+            latencies.tenants.len()
+        );
+    }
+    Ok(())
+}
+
+fn seeded_randomness<P: Picker>(build: impl Fn(u64, u64) -> P) -> anyhow::Result<()> {
+    let fleet = |mut p: P| {
+        for i in 0..30 {
+            p.register(BackendId(i), Health::Up);
+        }
+        p
+    };
+    let tenants: Vec<TenantId> = (0..100).map(TenantId).collect();
+    let picks = |p: &mut P| -> Vec<BackendId> {
+        tenants
+            .iter()
+            .flat_map(|&t| (0..10).map(move |_| t))
+            .map(|t| p.pick(t).unwrap().backend)
+            .collect()
+    };
+    let shards =
+        |p: &P| -> Vec<Vec<(BackendId, Health)>> { tenants.iter().map(|&t| p.shard(t)).collect() };
+
+    // The same seeds replay the same choices.
+    let (mut a, mut b) = (fleet(build(1, 0)), fleet(build(1, 0)));
+    if picks(&mut a) != picks(&mut b) {
+        bail!("pickers with the same seeds made different choices");
+    }
+    // A different RNG changes the choices but not the shards.
+    let (mut a, mut b) = (fleet(build(1, 0)), fleet(build(2, 0)));
+    if shards(&a) != shards(&b) {
+        bail!("changing the RNG changed shard membership");
+    }
+    if picks(&mut a) == picks(&mut b) {
+        bail!("pickers with different RNGs made identical choices");
+    }
+    // A different shard seed changes the shards.
+    let (a, b) = (fleet(build(1, 0)), fleet(build(1, 7)));
+    if shards(&a) == shards(&b) {
+        bail!("changing the shard seed did not change any shard");
+    }
+    Ok(())
+}
+
+/// A tenant's k-shard is a prefix of its (k+1)-shard, and tiered sizes are honoured.
+fn shard_prefixes<P: Picker>(with_shard_size: impl Fn(P, ShardSize) -> P) -> anyhow::Result<()> {
+    let register = |p: &mut P| {
+        for b in 0..30 {
+            let health = match b {
+                _ if b % 7 == 0 => Health::Draining,
+                _ if b % 11 == 0 => Health::Down,
+                _ => Health::Up,
+            };
+            p.register_weighted(BackendId(b), health, b as u32 % 3 + 1);
+        }
+    };
+
+    for k in 1..12 {
+        let mut smaller = with_shard_size(P::new(0), ShardSize::PerTenant(Box::new(move |_| k)));
+        let mut larger = with_shard_size(P::new(0), ShardSize::PerTenant(Box::new(move |_| k + 1)));
+        register(&mut smaller);
+        register(&mut larger);
+        for tenant_id in 0..200 {
+            let tenant_id = TenantId(tenant_id);
+            let small = smaller.shard(tenant_id);
+            let large = larger.shard(tenant_id);
+            if small.len() != k || !large.starts_with(&small) {
+                bail!(
+                    "{tenant_id:?} has {small:?} with shards of {k}, but {large:?} with one more"
+                );
+            }
+        }
+    }
+
+    // Big tenants get a dozen backends, everyone else the usual three. A tier of 0 still gets one backend.
+    let mut tenants: BTreeMap<TenantId, usize> = (0..10).map(|t| (TenantId(t), 12)).collect();
+    tenants.insert(TenantId(10), 0);
+    let mut p = with_shard_size(
+        P::new(0),
+        ShardSize::Tiers {
+            default: 3,
+            tenants,
+        },
+    );
+    register(&mut p);
+    for tenant_id in 0..200 {
+        let tenant_id = TenantId(tenant_id);
+        let shard = p.shard(tenant_id);
+        let expected = match tenant_id.0 {
+            0..10 => 12,
+            10 => 1,
+            _ => 3,
+        };
+        if shard.len() != expected {
+            bail!(
+                "{tenant_id:?} has {} backends instead of {expected}",
+                shard.len()
+            );
+        }
+        let serviceable = shard.iter().any(|&(_, h)| h == Health::Up);
+        for _ in 0..10 {
+            match p.pick(tenant_id) {
+                Ok(Pick { backend: b, .. }) if !shard.contains(&(b, Health::Up)) => {
+                    bail!("{tenant_id:?} was routed to {b:?}, outside the healthy members of {shard:?}");
+                }
+                Err(e) if serviceable => {
+                    bail!("{tenant_id:?} failed with {e:?}, but its shard is {shard:?}")
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Deploys four deploy groups of eight backends one group at a time: each group drains, comes back broken and
+/// down, and is then fixed. Every request must find a healthy backend throughout.
+fn deploy_groups<P: Picker>(
+    mut register: impl FnMut(&mut P, BackendId, u64, Health),
+) -> anyhow::Result<()> {
+    let mut p = P::new(3);
+    let group = |b: BackendId| b.0 % 4;
+    let backends: Vec<BackendId> = (0..32).map(BackendId).collect();
+    for &b in &backends {
+        register(&mut p, b, group(b), Health::Up);
+    }
+
+    for deploying in 0..4 {
+        for health in [Health::Draining, Health::Down, Health::Up] {
+            for &b in backends.iter().filter(|&&b| group(b) == deploying) {
+                register(&mut p, b, group(b), health);
+            }
+            for tenant_id in 0..2_000 {
+                let tenant_id = TenantId(tenant_id);
+                for _ in 0..5 {
+                    let b = match p.pick(tenant_id) {
+                        Ok(pick) => pick.backend,
+                        Err(e) => bail!(
+                            "could not route {tenant_id:?} with group {deploying} {health:?}: {e}"
+                        ),
+                    };
+                    if group(b) == deploying && health != Health::Up {
+                        bail!("tenant {tenant_id:?} got routed to {b:?} while its group was {health:?}");
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Three cells of ten backends. Tenants stay within their cell, a cell that goes down takes none of the others'
+/// tenants with it, and moving tenants between cells reports exactly who moved and who they now share with.
+fn cells() -> anyhow::Result<()> {
+    let mut router = CellRouter::<RendevouzShuffle>::with_cells(3, 3);
+    let cell_of = |b: BackendId| CellId(if b.0 < 30 { b.0 % 3 } else { 3 });
+    for b in (0..30).map(BackendId) {
+        router.register_in_cell(b, cell_of(b), Health::Up, 1);
+    }
+    let tenants: Vec<TenantId> = (0..300).map(TenantId).collect();
+    for &t in &tenants {
+        router.register_tenant(t);
+        let b = router.pick(t)?.backend;
+        if Some(cell_of(b)) != router.cell(t) {
+            bail!(
+                "{t:?} lives in {:?} but got routed to {b:?}",
+                router.cell(t)
+            );
+        }
+    }
+
+    for b in (0..30).map(BackendId).filter(|&b| cell_of(b) == CellId(0)) {
+        router.register(b, Health::Down);
+    }
+    for &t in &tenants {
+        match (router.cell(t), router.pick(t)) {
+            (Some(CellId(0)), Err(PickError::ShardUnavailable)) => {}
+            (Some(CellId(0)), picked) => bail!("{t:?} lives in a dead cell but got {picked:?}"),
+            (cell, Err(e)) => bail!("{t:?} lives in {cell:?}, which is up, but got {e}"),
+            _ => {}
+        }
+    }
+
+    // Rescue one tenant from the dead cell.
+    let stranded = *tenants
+        .iter()
+        .find(|&&t| router.cell(t) == Some(CellId(0)))
+        .unwrap();
+    let m = router.pin(stranded, CellId(1));
+    let shard: Vec<BackendId> = router.shard(stranded).into_iter().map(|(b, _)| b).collect();
+    if m.from != Some(CellId(0)) || m.to != Some(CellId(1)) || m.after != shard {
+        bail!("migrating {stranded:?} reported {m:?}, but its shard is now {shard:?}");
+    }
+    if m.exposed.is_empty() || m.exposed.iter().any(|&t| router.cell(t) != Some(CellId(1))) {
+        bail!("migrating {stranded:?} into cell 1 exposed {:?}", m.exposed);
+    }
+    if !m.after.contains(&router.pick(stranded)?.backend) {
+        bail!("{stranded:?} was not routed within its new shard");
+    }
+
+    // A new cell only takes tenants from the others, and retiring it sends exactly those back. Tenants that were
+    // only ever routed, never registered, aren't reported.
+    let unregistered = TenantId(1_000);
+    let _ = router.pick(unregistered);
+    let moved = router.add_cell(CellId(3), &(30..40).map(BackendId).collect::<Vec<_>>());
+    if moved.is_empty()
+        || moved
+            .iter()
+            .any(|m| m.to != Some(CellId(3)) || m.tenant == stranded || m.tenant == unregistered)
+    {
+        bail!("adding a cell moved {moved:?}");
+    }
+    let back = router.retire_cell(CellId(3));
+    let returned: Vec<(TenantId, Option<CellId>)> = back.iter().map(|m| (m.tenant, m.to)).collect();
+    let left: Vec<(TenantId, Option<CellId>)> = moved.iter().map(|m| (m.tenant, m.from)).collect();
+    if returned != left {
+        bail!("retiring the new cell sent back {returned:?}, expected {left:?}");
+    }
+
+    // Backends registered once every cell is gone get a cell of their own.
+    for cell in (0..3).map(CellId) {
+        router.retire_cell(cell);
+    }
+    router.register(BackendId(50), Health::Up);
+    if router.pick(stranded)?.backend != BackendId(50) {
+        bail!("a backend registered after every cell retired doesn't serve anyone");
+    }
+    let mut empty = CellRouter::<RendevouzShuffle>::with_cells(3, 0);
+    empty.register(BackendId(0), Health::Up);
+    if empty.pick(stranded)?.backend != BackendId(0) {
+        bail!("a backend registered on a router without cells doesn't serve anyone");
+    }
+    Ok(())
+}
+
+/// A poison-pill tenant and a healthy one share every backend, and the healthy tenant's requests land on the same
+/// backends while the poison pill's are still in flight. Only the poison pill is quarantined.
+fn breaker_blame() -> anyhow::Result<()> {
+    let mut p = CircuitBreaker::with_picker(<NaiveShuffle>::new(5));
+    for b in 0..5 {
+        p.register(BackendId(b), Health::Up);
+    }
+    let (poison, healthy) = (TenantId(0), TenantId(1));
+    for _ in 0..100 {
+        let poisoned = p.pick(poison);
+        let b = p.pick(healthy).unwrap().backend;
+        if let Ok(pick) = poisoned {
+            p.report_outcome(poison, pick.backend, Outcome::Error);
+        }
+        p.report_outcome(healthy, b, Outcome::Success);
+    }
+    if p.quarantined() != [poison] {
+        bail!(
+            "quarantined {:?} instead of just the poison pill",
+            p.quarantined()
+        );
+    }
+    Ok(())
+}
+
+/// A quarantined tenant stays out however much other traffic goes by, comes back once the clock passes its
+/// quarantine, and stays out twice as long after tripping again. A lone backend failing never trips anyone.
+fn quarantine_backoff() -> anyhow::Result<()> {
+    let now = Rc::new(Cell::new(SystemTime::UNIX_EPOCH));
+    let clock = Rc::clone(&now);
+    let mut p = CircuitBreaker::with_picker(<NaiveShuffle>::new(3))
+        .with_quarantine_time(Duration::from_secs(60))
+        .with_clock(move || clock.get());
+    for b in (0..10).map(BackendId) {
+        p.register(b, Health::Up);
+    }
+    let (poison, bystander) = (TenantId(0), TenantId(1));
+    let fail = |p: &mut CircuitBreaker<NaiveShuffle>| {
+        for (b, _) in p.shard(poison) {
+            p.report_outcome(poison, b, Outcome::Error);
+        }
+    };
+    let advance = |secs| now.set(now.get() + Duration::from_secs(secs));
+
+    fail(&mut p);
+    for _ in 0..100_000 {
+        p.pick(bystander).unwrap();
+    }
+    if p.pick(poison) != Err(PickError::TenantThrottled) {
+        bail!("a quarantine ran out with other tenants' traffic instead of with time");
+    }
+    advance(60);
+    let pick = p
+        .pick(poison)
+        .map_err(|e| anyhow::anyhow!("still quarantined after it ran out: {e:?}"))?;
+    p.report_outcome(poison, pick.backend, Outcome::Error);
+    advance(60);
+    if p.pick(poison).is_ok() {
+        bail!("a second quarantine lasted no longer than the first");
+    }
+    advance(60);
+    if p.pick(poison).is_err() {
+        bail!("a second quarantine lasted more than twice as long as the first");
+    }
+
+    let mut lone = CircuitBreaker::with_picker(<NaiveShuffle>::new(3));
+    lone.register(BackendId(0), Health::Up);
+    for _ in 0..10 {
+        lone.report_outcome(poison, BackendId(0), Outcome::Error);
+    }
+    if lone.pick(poison).is_err() {
+        bail!("one failing backend quarantined a tenant of a one-backend fleet");
+    }
+    Ok(())
+}
+
+/// An ejected backend stays out for its backoff however many picks go by, comes back once the clock passes it, and
+/// stays out twice as long after a second ejection.
+fn ejection_backoff() -> anyhow::Result<()> {
+    let now = Rc::new(Cell::new(SystemTime::UNIX_EPOCH));
+    let clock = Rc::clone(&now);
+    let mut p = OutlierDetection::with_picker(RoundRobin::new(3))
+        .with_ejection_time(Duration::from_secs(30))
+        .with_clock(move || clock.get());
+    for b in (0..10).map(BackendId) {
+        p.register(b, Health::Up);
+    }
+    let broken = BackendId(0);
+    let fail = |p: &mut OutlierDetection<RoundRobin>| {
+        for _ in 0..DEFAULT_CONSECUTIVE_ERRORS {
+            p.report_outcome(TenantId(0), broken, Outcome::Error);
+        }
+    };
+    let routes_to_broken = |p: &mut OutlierDetection<RoundRobin>, picks| {
+        (0..picks).any(|_| p.pick(TenantId(0)).unwrap().backend == broken)
+    };
+    let advance = |secs| now.set(now.get() + Duration::from_secs(secs));
+
+    fail(&mut p);
+    if routes_to_broken(&mut p, 100_000) {
+        bail!("an ejected backend came back before its backoff ran out");
+    }
+    advance(30);
+    if !routes_to_broken(&mut p, 100) {
+        bail!("an ejected backend stayed out after its backoff ran out");
+    }
+    fail(&mut p);
+    advance(30);
+    if routes_to_broken(&mut p, 100) {
+        bail!("a second ejection lasted no longer than the first");
+    }
+    advance(30);
+    if !routes_to_broken(&mut p, 100) {
+        bail!("a second ejection lasted more than twice as long as the first");
+    }
+    Ok(())
+}
+
+/// Pins tenants from an override table on disk: tenant 0 to an exclusive quarantine pool, tenant 1 to two shared
+/// backends, and tenant 2 to two backends of its own for the next minute. Pinned tenants stay on their backends,
+/// reserved backends serve nobody else until their pin expires, and reloading the table takes effect at once
+/// unless the new table is broken.
+fn overrides() -> anyhow::Result<()> {
+    let now = Rc::new(Cell::new(
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000),
+    ));
+    let clock = Rc::clone(&now);
+    let mut p =
+        Overrides::with_picker(<RendevouzShuffle>::new(3), 3).with_clock(move || clock.get());
+    for b in (0..30).map(BackendId) {
+        p.register(b, Health::Up);
+    }
+    let path = std::env::temp_dir().join(format!("flexss-overrides-{}.toml", std::process::id()));
+    let pins = "[pools.quarantine]\nbackends = [28, 29]\nexclusive = true\n\n\
+        [[pins]]\ntenant = 1\nbackends = [0, 1]\n\n\
+        [[pins]]\ntenant = 2\nbackends = [2, 3]\nexclusive = true\nexpires = 1000060\n";
+    std::fs::write(
+        &path,
+        format!("{pins}\n[[pins]]\ntenant = 0\npool = \"quarantine\"\n"),
+    )?;
+    p.load(&path)?;
+
+    let routed =
+        |p: &mut Overrides<RendevouzShuffle>, t: u64| -> anyhow::Result<BTreeSet<BackendId>> {
+            (0..50).map(|_| Ok(p.pick(TenantId(t))?.backend)).collect()
+        };
+    let ids = |ids: &[u64]| -> BTreeSet<BackendId> { ids.iter().copied().map(BackendId).collect() };
+    let general = |p: &mut Overrides<RendevouzShuffle>| -> anyhow::Result<BTreeSet<BackendId>> {
+        let mut touched = BTreeSet::new();
+        for t in 3..500 {
+            touched.insert(p.pick(TenantId(t))?.backend);
+        }
+        Ok(touched)
+    };
+
+    if routed(&mut p, 0)? != ids(&[28, 29])
+        || routed(&mut p, 1)? != ids(&[0, 1])
+        || routed(&mut p, 2)? != ids(&[2, 3])
+    {
+        bail!("pinned tenants strayed from their backends");
+    }
+    let touched = general(&mut p)?;
+    if !ids(&[2, 3, 28, 29]).is_disjoint(&touched) || !touched.contains(&BackendId(0)) {
+        bail!("the general pool reached {touched:?}");
+    }
+
+    // Once tenant 2's pin expires, it and its backends rejoin the general pool.
+    now.set(now.get() + Duration::from_secs(60));
+    if p.shard(TenantId(2))
+        .iter()
+        .all(|&(b, _)| b.0 == 2 || b.0 == 3)
+    {
+        bail!("tenant 2's shard outlived its pin");
+    }
+    if routed(&mut p, 2)?.is_subset(&ids(&[2, 3])) || !general(&mut p)?.contains(&BackendId(2)) {
+        bail!("tenant 2's pin outlived its expiry");
+    }
+
+    // A broken table leaves the old one in place, and a good one releases tenant 0.
+    std::fs::write(
+        &path,
+        format!("{pins}\n[[pins]]\ntenant = 0\npool = \"nowhere\"\n"),
+    )?;
+    if p.reload().is_ok() || routed(&mut p, 0)? != ids(&[28, 29]) {
+        bail!("reloading a broken table changed the overrides");
+    }
+    // Meanwhile the quarantine pool goes down, and takes only its own tenants with it.
+    p.register(BackendId(28), Health::Down);
+    p.register(BackendId(29), Health::Down);
+    if p.pick(TenantId(0)) != Err(PickError::ShardUnavailable) {
+        bail!("tenant 0 escaped its quarantine");
+    }
+    std::fs::write(&path, pins)?;
+    p.reload()?;
+    if !routed(&mut p, 0)?.is_disjoint(&ids(&[28, 29])) {
+        bail!("tenant 0 is still quarantined after its pin was removed");
+    }
+    std::fs::remove_file(&path)?;
+
+    // Reloading an unchanged pool keeps what its picker has learned, such as which backend to eject.
+    let mut p = Overrides::with_picker(OutlierDetection::with_picker(<Rendevouz>::new(3)), 3);
+    for b in (0..30).map(BackendId) {
+        p.register(b, Health::Up);
+    }
+    std::fs::write(&path, pins)?;
+    p.load(&path)?;
+    let bad = p.pick(TenantId(1))?.backend;
+    for _ in 0..DEFAULT_CONSECUTIVE_ERRORS {
+        p.report_outcome(TenantId(1), bad, Outcome::Error);
+    }
+    p.reload()?;
+    if p.pick(TenantId(1))?.backend == bad {
+        bail!("reloading the overrides readmitted an ejected backend");
+    }
+    // Tenant 1's errors were its pool's business: the general pool still routes to the backend.
+    if !(3..500).any(|t| p.pick(TenantId(t)).is_ok_and(|pick| pick.backend == bad)) {
+        bail!("a pinned tenant's errors ejected {bad:?} from the general pool");
+    }
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+fn zone_outage<P: Picker>(
+    mut register: impl FnMut(&mut P, BackendId, ZoneId, Health),
+) -> anyhow::Result<()> {
+    // 30 backends spread over three zones, and then one zone goes dark.
+    let mut s = Simulation::default();
+    let mut p = P::new(3);
+    let zone = |b: BackendId| ZoneId(b.0 % 3);
+    let backends: Vec<BackendId> = (0..30).map(BackendId).collect();
+    for &b in &backends {
+        s.backends.insert(b, Health::Up);
+        register(&mut p, b, zone(b), Health::Up);
+    }
+    for &b in backends.iter().filter(|&&b| zone(b) == ZoneId(0)) {
+        *s.backends.get_mut(&b).unwrap() = Health::Down;
+        register(&mut p, b, zone(b), Health::Down);
+    }
+
+    for tenant_id in 0..2_000 {
+        let tenant_id = TenantId(tenant_id);
+        for _ in 0..10 {
+            let b = match p.pick(tenant_id) {
+                Ok(pick) => pick.backend,
+                Err(e) => bail!("could not route request for {tenant_id:?}: {e}"),
+            };
+            if s.backends.get(&b).unwrap() != &Health::Up {
+                bail!("tenant {tenant_id:?} got routed to an unhealthy backend");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `LeastLoaded` only asks its inner picker to pick when the shard has no healthy member, to learn why.
+fn balanced_picks() -> anyhow::Result<()> {
+    let inner = CountedPicks::new(3);
+    let picks = Rc::clone(&inner.picks);
+    let mut p = LeastLoaded::with_rng(inner, SmallRng::seed_from_u64(1));
+    for i in 0..10 {
+        p.register(BackendId(i), Health::Up);
+    }
+    p.register(BackendId(0), Health::Draining);
+    let mut fallbacks = 0;
+    for tenant_id in (0..100).map(TenantId) {
+        let shard = p.shard(tenant_id);
+        for _ in 0..10 {
+            let pick = p.pick(tenant_id)?;
+            if !shard.iter().any(|&(id, _)| id == pick.backend) {
+                bail!("{tenant_id:?}: picked {pick:?} outside {shard:?}");
+            }
+            fallbacks += usize::from(pick.fallback);
+            p.on_request_complete(pick.backend);
+        }
+    }
+    if picks.get() != 0 || fallbacks == 0 {
+        bail!("{} inner picks and {fallbacks} fallbacks", picks.get());
+    }
+    for i in 0..10 {
+        p.register(BackendId(i), Health::Down);
+    }
+    if p.pick(TenantId(0)) != Err(PickError::ShardUnavailable) || picks.get() != 1 {
+        bail!(
+            "a dead shard gave {:?} after {} inner picks",
+            p.pick(TenantId(0)),
+            picks.get()
+        );
+    }
+    Ok(())
+}
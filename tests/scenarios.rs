@@ -0,0 +1,264 @@
+//! Plays every scenario in `scenarios/` against the pickers, and checks which ones each picker copes with.
+
+use std::path::Path;
+
+use flexss::{
+    block_picker::BlockPicker,
+    bounded_load::BoundedLoadRendezvous,
+    circuit_breaker::CircuitBreaker,
+    concurrent::ConcurrentRendevouzShuffle,
+    drain_aware_shuffle::DrainAwareShuffle,
+    jump_hash::{JumpHash, JumpShuffle},
+    least_loaded::LeastLoaded,
+    maglev::{Maglev, MaglevShuffle},
+    multi_probe::{MultiProbe, MultiProbeShuffle},
+    naive_shuffle::NaiveShuffle,
+    outlier::OutlierDetection,
+    rendevouz::Rendevouz,
+    rendevouz_shuffle::RendevouzShuffle,
+    ring::{Ring, RingShuffle},
+    scenario::Scenario,
+    zoned_shuffle::ZonedShuffle,
+    Picker, RoundRobin,
+};
+
+#[test]
+fn health_aware() {
+    scenario::<RoundRobin>("health_aware").unwrap();
+    scenario::<NaiveShuffle>("health_aware").unwrap();
+    scenario::<BlockPicker>("health_aware").unwrap();
+    scenario::<Rendevouz>("health_aware").unwrap();
+    scenario::<Maglev>("health_aware").unwrap();
+    scenario::<JumpHash>("health_aware").unwrap();
+    scenario::<MultiProbe>("health_aware").unwrap();
+    scenario::<Ring>("health_aware").unwrap();
+    scenario::<BoundedLoadRendezvous>("health_aware").unwrap();
+    scenario::<RendevouzShuffle>("health_aware").unwrap();
+    scenario::<MaglevShuffle>("health_aware").unwrap();
+    scenario::<JumpShuffle>("health_aware").unwrap();
+    scenario::<MultiProbeShuffle>("health_aware").unwrap();
+    scenario::<RingShuffle>("health_aware").unwrap();
+    scenario::<LeastLoaded<RendevouzShuffle>>("health_aware").unwrap();
+    scenario::<ConcurrentRendevouzShuffle>("health_aware").unwrap();
+    scenario::<ZonedShuffle>("health_aware").unwrap();
+}
+
+#[test]
+fn poison_pill() {
+    // RoundRobin is succeptible to poison pill tenants
+    assert!(scenario::<RoundRobin>("poison_pill").is_err());
+    // These pickers all prevent poison pills at steady state
+    scenario::<NaiveShuffle>("poison_pill").unwrap();
+    scenario::<DrainAwareShuffle>("poison_pill").unwrap();
+    scenario::<BlockPicker>("poison_pill").unwrap();
+    // Rendevouz hashing lets one backend murder everything
+    assert!(scenario::<Rendevouz>("poison_pill").is_err());
+    // and so does every other picker that sends a tenant to one backend
+    assert!(scenario::<Maglev>("poison_pill").is_err());
+    assert!(scenario::<JumpHash>("poison_pill").is_err());
+    assert!(scenario::<MultiProbe>("poison_pill").is_err());
+    assert!(scenario::<Ring>("poison_pill").is_err());
+    assert!(scenario::<BoundedLoadRendezvous>("poison_pill").is_err());
+    scenario::<RendevouzShuffle>("poison_pill").unwrap();
+    scenario::<MaglevShuffle>("poison_pill").unwrap();
+    scenario::<JumpShuffle>("poison_pill").unwrap();
+    scenario::<MultiProbeShuffle>("poison_pill").unwrap();
+    scenario::<RingShuffle>("poison_pill").unwrap();
+    scenario::<LeastLoaded<RendevouzShuffle>>("poison_pill").unwrap();
+    scenario::<ConcurrentRendevouzShuffle>("poison_pill").unwrap();
+    scenario::<ZonedShuffle>("poison_pill").unwrap();
+}
+
+// Millions of requests for every picker. The scorecard plays this scenario on every run; here it takes `--ignored`.
+#[test]
+#[ignore = "slow: run with `cargo test --release -- --ignored`"]
+fn unaligned_rolling_restart() {
+    scenario::<RoundRobin>("unaligned_rolling_restart").unwrap();
+    // NaiveShuffle cannot distinguish between intentional
+    // deploys and poison-pill scenarios, so it hits dead shards.
+    assert!(scenario::<NaiveShuffle>("unaligned_rolling_restart").is_err());
+    // Making the picker aware of drains allows it to work with
+    // intentional deploys.
+    scenario::<DrainAwareShuffle>("unaligned_rolling_restart").unwrap();
+    // Draining backends hand their place in a block to the
+    // next one down, so the BlockPicker copes too.
+    scenario::<BlockPicker>("unaligned_rolling_restart").unwrap();
+    scenario::<Rendevouz>("unaligned_rolling_restart").unwrap();
+    scenario::<Maglev>("unaligned_rolling_restart").unwrap();
+    scenario::<JumpHash>("unaligned_rolling_restart").unwrap();
+    scenario::<MultiProbe>("unaligned_rolling_restart").unwrap();
+    scenario::<Ring>("unaligned_rolling_restart").unwrap();
+    scenario::<BoundedLoadRendezvous>("unaligned_rolling_restart").unwrap();
+    scenario::<RendevouzShuffle>("unaligned_rolling_restart").unwrap();
+    scenario::<MaglevShuffle>("unaligned_rolling_restart").unwrap();
+    scenario::<JumpShuffle>("unaligned_rolling_restart").unwrap();
+    scenario::<MultiProbeShuffle>("unaligned_rolling_restart").unwrap();
+    scenario::<RingShuffle>("unaligned_rolling_restart").unwrap();
+    scenario::<LeastLoaded<RendevouzShuffle>>("unaligned_rolling_restart").unwrap();
+    scenario::<ConcurrentRendevouzShuffle>("unaligned_rolling_restart").unwrap();
+    scenario::<ZonedShuffle>("unaligned_rolling_restart").unwrap();
+}
+
+#[test]
+fn rolling_restart_blast_radius() {
+    // RoundRobin always hits a ton of backends
+    assert!(scenario::<RoundRobin>("rolling_restart_blast_radius").is_err());
+    // NaiveShuffle is good at dealing with ephemeral downtime
+    scenario::<NaiveShuffle>("rolling_restart_blast_radius").unwrap();
+    scenario::<BlockPicker>("rolling_restart_blast_radius").unwrap();
+    // The drain-aware shuffle picker can deal with lots of unhealthy backends,
+    // but the cost is that it sprawls.
+    assert!(scenario::<DrainAwareShuffle>("rolling_restart_blast_radius").is_err());
+    scenario::<Rendevouz>("rolling_restart_blast_radius").unwrap();
+    scenario::<Maglev>("rolling_restart_blast_radius").unwrap();
+    scenario::<JumpHash>("rolling_restart_blast_radius").unwrap();
+    scenario::<MultiProbe>("rolling_restart_blast_radius").unwrap();
+    scenario::<Ring>("rolling_restart_blast_radius").unwrap();
+    scenario::<BoundedLoadRendezvous>("rolling_restart_blast_radius").unwrap();
+    scenario::<RendevouzShuffle>("rolling_restart_blast_radius").unwrap();
+    scenario::<MaglevShuffle>("rolling_restart_blast_radius").unwrap();
+    scenario::<JumpShuffle>("rolling_restart_blast_radius").unwrap();
+    scenario::<MultiProbeShuffle>("rolling_restart_blast_radius").unwrap();
+    scenario::<RingShuffle>("rolling_restart_blast_radius").unwrap();
+    scenario::<LeastLoaded<RendevouzShuffle>>("rolling_restart_blast_radius").unwrap();
+    scenario::<ConcurrentRendevouzShuffle>("rolling_restart_blast_radius").unwrap();
+    scenario::<ZonedShuffle>("rolling_restart_blast_radius").unwrap();
+}
+
+#[test]
+fn recycle_blast_radius() {
+    // Every one of these struggles with a quick recycling
+    assert!(scenario::<RoundRobin>("recycle_blast_radius").is_err());
+    assert!(scenario::<NaiveShuffle>("recycle_blast_radius").is_err());
+    assert!(scenario::<DrainAwareShuffle>("recycle_blast_radius").is_err());
+    // But rendevouz hashing (and other consistent hashing approaches)
+    // have a very limited blast radius even when the underlying fleet
+    // changes.
+    scenario::<Rendevouz>("recycle_blast_radius").unwrap();
+    scenario::<Maglev>("recycle_blast_radius").unwrap();
+    scenario::<JumpHash>("recycle_blast_radius").unwrap();
+    scenario::<MultiProbe>("recycle_blast_radius").unwrap();
+    scenario::<Ring>("recycle_blast_radius").unwrap();
+    scenario::<BoundedLoadRendezvous>("recycle_blast_radius").unwrap();
+    scenario::<RendevouzShuffle>("recycle_blast_radius").unwrap();
+    scenario::<MaglevShuffle>("recycle_blast_radius").unwrap();
+    scenario::<JumpShuffle>("recycle_blast_radius").unwrap();
+    scenario::<MultiProbeShuffle>("recycle_blast_radius").unwrap();
+    scenario::<RingShuffle>("recycle_blast_radius").unwrap();
+    scenario::<LeastLoaded<RendevouzShuffle>>("recycle_blast_radius").unwrap();
+    scenario::<ConcurrentRendevouzShuffle>("recycle_blast_radius").unwrap();
+    scenario::<ZonedShuffle>("recycle_blast_radius").unwrap();
+    // Blocks rank their members by rendezvous score too, so recycling a
+    // backend only moves the tenants that had it in their shard.
+    scenario::<BlockPicker>("recycle_blast_radius").unwrap();
+}
+
+#[test]
+fn load_distribution() {
+    scenario::<RoundRobin>("load_distribution").unwrap();
+    scenario::<NaiveShuffle>("load_distribution").unwrap();
+    scenario::<BlockPicker>("load_distribution").unwrap();
+    assert!(scenario::<Rendevouz>("load_distribution").is_err());
+    // Like rendevouz hashing, Maglev, jump, multi-probe and ring hashing map
+    // each tenant to a single backend.
+    assert!(scenario::<Maglev>("load_distribution").is_err());
+    assert!(scenario::<JumpHash>("load_distribution").is_err());
+    assert!(scenario::<MultiProbe>("load_distribution").is_err());
+    assert!(scenario::<Ring>("load_distribution").is_err());
+    scenario::<BoundedLoadRendezvous>("load_distribution").unwrap();
+    scenario::<RendevouzShuffle>("load_distribution").unwrap();
+    scenario::<MaglevShuffle>("load_distribution").unwrap();
+    scenario::<JumpShuffle>("load_distribution").unwrap();
+    scenario::<MultiProbeShuffle>("load_distribution").unwrap();
+    scenario::<RingShuffle>("load_distribution").unwrap();
+    scenario::<LeastLoaded<RendevouzShuffle>>("load_distribution").unwrap();
+    scenario::<ConcurrentRendevouzShuffle>("load_distribution").unwrap();
+    scenario::<ZonedShuffle>("load_distribution").unwrap();
+}
+
+#[test]
+fn outlier_ejection() {
+    // Nobody tells the picker about a crash, so only outlier detection
+    // notices and stops sending requests to the crashed backends.
+    assert!(scenario::<RoundRobin>("outlier_ejection").is_err());
+    assert!(scenario::<NaiveShuffle>("outlier_ejection").is_err());
+    assert!(scenario::<RendevouzShuffle>("outlier_ejection").is_err());
+    assert!(scenario::<LeastLoaded<RendevouzShuffle>>("outlier_ejection").is_err());
+    scenario::<OutlierDetection<RoundRobin>>("outlier_ejection").unwrap();
+    scenario::<OutlierDetection<NaiveShuffle>>("outlier_ejection").unwrap();
+    scenario::<OutlierDetection<RendevouzShuffle>>("outlier_ejection").unwrap();
+    scenario::<OutlierDetection<LeastLoaded<RendevouzShuffle>>>("outlier_ejection").unwrap();
+}
+
+#[test]
+fn failing_tenant() {
+    // A tenant whose requests fail everywhere makes every backend it touches
+    // look like an outlier. Capping ejections keeps the fleet, and even its
+    // own shard, routable,
+    scenario::<OutlierDetection<RoundRobin>>("failing_tenant").unwrap();
+    scenario::<OutlierDetection<NaiveShuffle>>("failing_tenant").unwrap();
+    scenario::<OutlierDetection<RendevouzShuffle>>("failing_tenant").unwrap();
+    // but without the cap it ejects everything it can reach.
+    let mut uncapped = OutlierDetection::with_picker(RoundRobin::new(5)).with_max_ejected(1.0);
+    assert!(scenario_with("failing_tenant", &mut uncapped).is_err());
+    let mut uncapped = OutlierDetection::with_picker(<NaiveShuffle>::new(5)).with_max_ejected(1.0);
+    assert!(scenario_with("failing_tenant", &mut uncapped).is_err());
+}
+
+#[test]
+fn poison_pill_quarantine() {
+    // Sharding keeps a poison pill to its own shard, but it takes those
+    // backends down again as soon as they come back,
+    assert!(scenario::<RoundRobin>("poison_pill_quarantine").is_err());
+    assert!(scenario::<NaiveShuffle>("poison_pill_quarantine").is_err());
+    assert!(scenario::<RendevouzShuffle>("poison_pill_quarantine").is_err());
+    // and ejecting backends doesn't help when the fault is the tenant's.
+    assert!(scenario::<OutlierDetection<RendevouzShuffle>>("poison_pill_quarantine").is_err());
+    // Breaking the circuit for the tenant itself does.
+    scenario::<CircuitBreaker<RoundRobin>>("poison_pill_quarantine").unwrap();
+    scenario::<CircuitBreaker<NaiveShuffle>>("poison_pill_quarantine").unwrap();
+    scenario::<CircuitBreaker<RendevouzShuffle>>("poison_pill_quarantine").unwrap();
+    scenario::<CircuitBreaker<OutlierDetection<RendevouzShuffle>>>("poison_pill_quarantine")
+        .unwrap();
+    scenario::<CircuitBreaker<NaiveShuffle>>("health_aware").unwrap();
+    scenario::<CircuitBreaker<NaiveShuffle>>("poison_pill").unwrap();
+}
+
+#[test]
+fn malformed_scenarios() {
+    // Scenario files are operator input: what can't be played is rejected up
+    // front rather than crashing halfway through.
+    let timeline = |event: &str| {
+        Scenario::parse(&format!(
+            "name = \"malformed\"\nbackends = 10\nshard_size = 3\n\n[[timeline]]\n{event}\n"
+        ))
+    };
+    timeline("event = \"register\"\nbackends = [10]\nweight = 2").unwrap();
+    assert!(timeline("event = \"register\"\nbackends = [10]\nweight = 0").is_err());
+    let restart = |batch: usize, stride: usize| {
+        timeline(&format!(
+            "event = \"rolling_restart\"\nbatch = {batch}\nstride = {stride}\nrequests = 1"
+        ))
+    };
+    restart(10, 1).unwrap();
+    assert!(restart(0, 1).is_err());
+    assert!(restart(2, 0).is_err());
+    assert!(restart(11, 1).is_err());
+}
+
+/// Runs `scenarios/<name>.toml`.
+fn scenario<P: Picker>(name: &str) -> anyhow::Result<()> {
+    scenario_with(name, &mut P::new(load(name).shard_size))
+}
+
+/// Runs `scenarios/<name>.toml` against a picker that has already been configured.
+fn scenario_with<P: Picker>(name: &str, picker: &mut P) -> anyhow::Result<()> {
+    load(name).run_with(picker)
+}
+
+fn load(name: &str) -> Scenario {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("scenarios")
+        .join(format!("{name}.toml"));
+    Scenario::load(path).unwrap()
+}